@@ -0,0 +1,115 @@
+extern crate minifb;
+extern crate learning_graphics;
+
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+
+use learning_graphics::gfx::colors::{Color, from_u8_rgb};
+use learning_graphics::gfx::render_2d::Viewport;
+use learning_graphics::gfx::render_3d::{Model, Instance, MaterialData, CullMode, Camera};
+use learning_graphics::gfx::lighting::{Shading, Light};
+
+const WINDOW_WIDTH: usize = 640;
+const WINDOW_HEIGHT: usize = 640;
+
+const BACKGROUND_COLOR: Color = from_u8_rgb(255, 255, 255);
+
+const MOVE_SPEED: f64 = 0.1;
+//radians per pixel of mouse movement
+const MOUSE_SPEED: f64 = 0.005;
+
+//walks around a field of cubes: cargo run --features window --example camera
+//
+//WASD moves, space and left shift go up and down, dragging with the left
+//mouse button looks around. Tab switches to orbiting around the middle of
+//the field, where dragging swings the camera around it and the scroll
+//wheel or W/S zoom.
+fn main() {
+    let cube_model = Model::cube();
+
+    let mut cubes = vec![];
+    for i in 0..5 {
+        for j in 0..5 {
+            cubes.push(Instance {
+                model: &cube_model,
+                scale: 0.5,
+                r_pitch: 0.0,
+                r_yaw: (i * j) as f64 * 0.3,
+                r_roll: 0.0,
+                x: (i as f64 - 2.0) * 3.0,
+                y: 0.0,
+                z: (j as f64 - 2.0) * 3.0,
+                material: MaterialData::Flat(vec![from_u8_rgb(50 * i as u8 + 50, 50 * j as u8 + 50, 120); 12]),
+                cull_mode: CullMode::Back,
+                shading: Shading::Flat
+            });
+        }
+    }
+
+    let mut viewport = Viewport::new(
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        2.0,
+        2.0,
+        1.0,
+        BACKGROUND_COLOR
+    );
+    viewport.lights = vec![
+        Light::Ambient {intensity: [0.3; 3]},
+        Light::Directional {direction: [-0.5, -1.0, 0.8], intensity: [0.7; 3]}
+    ];
+
+    let mut window = Window::new(
+        "Camera - WASD, mouse drag, Tab to orbit, ESC to exit",
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        WindowOptions::default(),
+    )
+    .unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+
+    let target = [0.0, 0.0, 0.0];
+    let mut camera = Camera::new([0.0, 2.0, -12.0]);
+    camera.look_at(target);
+    let mut orbiting = false;
+    let mut last_mouse = None;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if window.is_key_pressed(Key::Tab, KeyRepeat::No) {
+            orbiting = !orbiting;
+            if orbiting {
+                camera.look_at(target);
+            }
+        }
+
+        let forward = if window.is_key_down(Key::W) {MOVE_SPEED} else if window.is_key_down(Key::S) {-MOVE_SPEED} else {0.0};
+        let right = if window.is_key_down(Key::D) {MOVE_SPEED} else if window.is_key_down(Key::A) {-MOVE_SPEED} else {0.0};
+        let up = if window.is_key_down(Key::Space) {MOVE_SPEED} else if window.is_key_down(Key::LeftShift) {-MOVE_SPEED} else {0.0};
+
+        //screen y grows downwards, pitch grows upwards
+        let mouse = window.get_mouse_pos(MouseMode::Pass);
+        let (dx, dy) = match (last_mouse, mouse) {
+            (Some((x0, y0)), Some((x1, y1))) if window.get_mouse_down(MouseButton::Left) =>
+                ((x1 - x0) as f64 * MOUSE_SPEED, (y0 - y1) as f64 * MOUSE_SPEED),
+            _ => (0.0, 0.0)
+        };
+        last_mouse = mouse;
+
+        if orbiting {
+            let scroll = window.get_scroll_wheel().map_or(0.0, |(_, y)| y as f64);
+            camera.orbit(target, dx + right * 0.2, dy + up * 0.2);
+            camera.dolly(forward + scroll * MOVE_SPEED);
+        } else {
+            camera.turn(dx, dy);
+            camera.walk(forward, right, up);
+        }
+
+        let view_matrix = camera.view_matrix();
+        for cube in &cubes {
+            cube.render(&mut viewport, view_matrix);
+        }
+
+        viewport.present(&mut window).unwrap();
+    }
+}
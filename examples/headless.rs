@@ -0,0 +1,78 @@
+extern crate learning_graphics;
+
+use std::env;
+use std::fs;
+
+use learning_graphics::gfx::colors::{Color, from_u8_rgb};
+use learning_graphics::gfx::render_2d::Viewport;
+use learning_graphics::gfx::render_3d::{Model, Instance, MaterialData, CullMode};
+use learning_graphics::gfx::lighting::Shading;
+use learning_graphics::gfx::render_target::TgaFrames;
+use learning_graphics::gfx::save_tga::{save_bitmap_as_tga, TgaOptions};
+
+const CANVAS_WIDTH: usize = 320;
+const CANVAS_HEIGHT: usize = 320;
+
+const BACKGROUND_COLOR: Color = from_u8_rgb(255, 255, 255);
+
+//renders a spinning cube without opening a window and writes the frames
+//as tga files: cargo run --example headless -- <output directory>
+fn main() {
+    let directory = env::args().nth(1).unwrap_or_else(|| "frames".to_string());
+    fs::create_dir_all(&directory).unwrap();
+
+    let cube_model = Model::cube();
+
+    let mut cube_instance = Instance {
+        model: &cube_model,
+        scale: 1.0,
+        r_pitch: 0.4,
+        r_yaw: 0.0,
+        r_roll: 0.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        material: MaterialData::Flat(vec![
+            from_u8_rgb(255, 0, 0),
+            from_u8_rgb(255, 0, 0),
+            from_u8_rgb(0, 255, 0),
+            from_u8_rgb(0, 255, 0),
+            from_u8_rgb(0, 0, 255),
+            from_u8_rgb(0, 0, 255),
+            from_u8_rgb(255, 255, 0),
+            from_u8_rgb(255, 255, 0),
+            from_u8_rgb(230, 230, 250),
+            from_u8_rgb(230, 230, 250),
+            from_u8_rgb(0, 255, 255),
+            from_u8_rgb(0, 255, 255)
+        ]),
+        cull_mode: CullMode::Back,
+        shading: Shading::Unlit
+    };
+
+    let mut viewport = Viewport::new(
+        CANVAS_WIDTH,
+        CANVAS_HEIGHT,
+        6.0,
+        6.0,
+        1.0,
+        BACKGROUND_COLOR
+    );
+
+    let camera = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 5.0, 1.0],
+    ];
+
+    let mut frames = TgaFrames::new(&directory, "frame_");
+    viewport.render_frames(&mut frames, 30, |view, frame| {
+        cube_instance.r_yaw = frame as f64 * 0.1;
+        cube_instance.render(view, camera);
+        if frame == 29 {
+            let depth = view.depth_bitmap();
+            save_bitmap_as_tga(&depth, format!("{}/depth.tga", directory), TgaOptions::default()).unwrap();
+        }
+    }).unwrap();
+}
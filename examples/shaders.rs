@@ -0,0 +1,97 @@
+extern crate learning_graphics;
+
+use std::env;
+use std::fs;
+
+use learning_graphics::gfx::colors::{Color, from_u8_rgb};
+use learning_graphics::gfx::render_2d::Viewport;
+use learning_graphics::gfx::render_3d::{Model, Instance, MaterialData, CullMode, Fragment};
+use learning_graphics::gfx::shaders::{Uniforms, Corner, VertexShader, FragmentShader};
+use learning_graphics::gfx::lighting::Shading;
+use learning_graphics::gfx::render_target::TgaFrames;
+use learning_graphics::math::{Vector4, col_mat4_transform};
+
+const CANVAS_WIDTH: usize = 320;
+const CANVAS_HEIGHT: usize = 320;
+
+const BACKGROUND_COLOR: Color = from_u8_rgb(255, 255, 255);
+
+//twists the model around its y axis, more the further up a vertex is, and
+//hands its model space position to the fragment shader
+struct Twist {
+    angle: f64
+}
+
+impl VertexShader for Twist {
+    fn shade(&self, corner: &Corner, varyings: &mut Vec<f64>) -> Vector4 {
+        let [x, y, z, w] = corner.position();
+        let (sin, cos) = (self.angle * y).sin_cos();
+        varyings.extend([x, y, z]);
+        let eye = col_mat4_transform(corner.uniforms.to_camera, [x * cos - z * sin, y, x * sin + z * cos, w]);
+        col_mat4_transform(corner.uniforms.projection, [eye[0], eye[1], eye[2], 1.0])
+    }
+
+    //the twist keeps vertices as far from the y axis as they were, but not
+    //necessarily from the center of the model's bounding sphere
+    fn stays_in_bounds(&self) -> bool {
+        false
+    }
+}
+
+//horizontal stripes, fading from red at the bottom to blue at the top
+struct Stripes;
+
+impl FragmentShader for Stripes {
+    fn shade(&self, _uniforms: &Uniforms, _triangle: usize, fragment: &Fragment) -> Option<Color> {
+        let y = fragment.varyings[1];
+        if (y * 4.0).rem_euclid(1.0) < 0.25 {
+            return Some(from_u8_rgb(40, 40, 40));
+        }
+        let t = (y + 1.0) / 2.0;
+        Some(from_u8_rgb((255.0 * (1.0 - t)) as u8, 60, (255.0 * t) as u8))
+    }
+}
+
+//renders a cube drawn by custom shaders and writes the frames as tga
+//files: cargo run --example shaders -- <output directory>
+fn main() {
+    let directory = env::args().nth(1).unwrap_or_else(|| "frames".to_string());
+    fs::create_dir_all(&directory).unwrap();
+
+    let cube_model = Model::cube();
+
+    let mut viewport = Viewport::new(
+        CANVAS_WIDTH,
+        CANVAS_HEIGHT,
+        6.0,
+        6.0,
+        1.0,
+        BACKGROUND_COLOR
+    );
+
+    let camera = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 5.0, 1.0],
+    ];
+
+    let mut frames = TgaFrames::new(&directory, "frame_");
+    viewport.render_frames(&mut frames, 30, |view, frame| {
+        let twist = Twist {angle: (frame as f64 * 0.2).sin() * 0.6};
+        let cube_instance = Instance {
+            model: &cube_model,
+            scale: 1.0,
+            r_pitch: 0.4,
+            r_yaw: frame as f64 * 0.1,
+            r_roll: 0.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            material: MaterialData::Shaded(&twist, &Stripes),
+            cull_mode: CullMode::Back,
+            shading: Shading::Unlit
+        };
+        cube_instance.render(view, camera);
+    }).unwrap();
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use super::colors::{Color, from_rgba_u8, from_u8_rgba};
 #[derive(Clone)]
 pub struct Bitmap {
@@ -6,7 +7,18 @@ pub struct Bitmap {
     pub data: Vec<Color>
 }
 
+//the pixels would drown out everything else in a debug print
+impl fmt::Debug for Bitmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bitmap")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Bitmap {
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_rectangle_on(&self, target: &mut Bitmap, tx: usize, ty: usize, sx: usize, sy: usize,  sw: usize, sh: usize){
         
         for i in 0..sh{
@@ -0,0 +1,206 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::{self, File};
+use std::path::Path;
+
+use super::colors::{from_u8_rgba, from_rgba_u8};
+use super::bitmaps::{Bitmap};
+use super::image_error::{ImageError, ImageResult};
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    (bytes[1] as u16) << 8 | bytes[0] as u16
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    (bytes[3] as u32) << 24 | (bytes[2] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[0] as u32
+}
+
+//pulls an 8-bit channel out of a pixel using one of the bitfield masks
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let value = (pixel & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+    (value as u64 * 255 / max as u64) as u8
+}
+
+pub fn load_bitmap_from_bmp<P: AsRef<Path>>(filename: P) -> ImageResult<Bitmap> {
+    decode_bmp(&fs::read(filename)?)
+}
+
+pub fn decode_bmp(file: &[u8]) -> ImageResult<Bitmap> {
+    if file.len() < 2 || &file[..2] != b"BM" {
+        return Err(ImageError::Corrupt("missing bmp signature".to_string()));
+    }
+    let header = file.get(..18).ok_or(ImageError::Truncated)?;
+    let pixel_offset = le_u32(&header[10..14]) as usize;
+    let info_size = le_u32(&header[14..18]) as usize;
+    //the old 12 byte OS/2 header has 16-bit dimensions and no compression
+    if info_size < 40 {
+        return Err(ImageError::UnsupportedType(info_size as u32));
+    }
+    let info = file.get(14..14+info_size).ok_or(ImageError::Truncated)?;
+
+    let width = le_u32(&info[4..8]) as i32;
+    let height = le_u32(&info[8..12]) as i32;
+    let bpp = le_u16(&info[14..16]) as u32;
+    let compression = le_u32(&info[16..20]);
+
+    if width <= 0 || height == 0 {
+        return Err(ImageError::Corrupt(format!("bad dimensions {}x{}", width, height)));
+    }
+    if !matches!(bpp, 24 | 32) {
+        return Err(ImageError::UnsupportedBitDepth(bpp));
+    }
+    //a negative height means the rows are stored top to bottom
+    let top_down = height < 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+    //BI_RGB 32-bit files leave the fourth byte unused, only trust it as
+    //alpha if a mask says so
+    let masks = match compression {
+        BI_RGB => [0xff0000, 0xff00, 0xff, 0],
+        BI_BITFIELDS if bpp == 32 => {
+            //the masks follow a 40 byte header, or are part of a v4/v5 one
+            let m = file.get(54..70).ok_or(ImageError::Truncated)?;
+            let alpha = if info_size >= 56 {le_u32(&m[12..16])} else {0};
+            [le_u32(&m[0..4]), le_u32(&m[4..8]), le_u32(&m[8..12]), alpha]
+        },
+        _ => return Err(ImageError::UnsupportedType(compression))
+    };
+
+    let bytes_per_pixel = bpp as usize / 8;
+    //rows are padded to a multiple of four bytes. The sizes come straight
+    //from the header, so the arithmetic is checked and all of the pixel
+    //data has to be there before anything gets allocated.
+    let row = width.checked_mul(bytes_per_pixel);
+    let stride = row.and_then(|row| row.checked_add(3)).map(|row| row & !3);
+    let end = stride.and_then(|stride| stride.checked_mul(height - 1))
+        .and_then(|size| size.checked_add(row?))
+        .and_then(|size| size.checked_add(pixel_offset));
+    let (stride, end) = match (stride, end) {
+        (Some(stride), Some(end)) => (stride, end),
+        _ => return Err(ImageError::Corrupt(format!("bad dimensions {}x{}", width, height)))
+    };
+    let pixels = file.get(pixel_offset..end).ok_or(ImageError::Truncated)?;
+
+    let mut data = vec![0u32; width * height];
+    for row in 0..height {
+        let y = if top_down {row} else {height - 1 - row};
+        let line = &pixels[row * stride..];
+        for (x, item) in data[y*width..(y+1)*width].iter_mut().enumerate() {
+            let p = &line[x * bytes_per_pixel..(x + 1) * bytes_per_pixel];
+            *item = if bpp == 24 {
+                from_u8_rgba(p[2], p[1], p[0], 255)
+            } else {
+                let pixel = le_u32(p);
+                let alpha = if masks[3] == 0 {255} else {channel(pixel, masks[3])};
+                from_u8_rgba(
+                    channel(pixel, masks[0]),
+                    channel(pixel, masks[1]),
+                    channel(pixel, masks[2]),
+                    alpha
+                )
+            };
+        }
+    }
+
+    Ok(Bitmap {width, height, data})
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BmpOptions {
+    //32 bits per pixel with an alpha mask if true, 24 otherwise
+    pub alpha: bool,
+    pub top_down: bool
+}
+
+pub fn save_bitmap_as_bmp<P: AsRef<Path>>(bitmap: &Bitmap, filename: P, options: BmpOptions) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(filename)?);
+    write_bmp(bitmap, &mut f, options)?;
+    f.flush()
+}
+
+pub fn write_bmp<W: Write>(bitmap: &Bitmap, f: &mut W, options: BmpOptions) -> io::Result<()> {
+    let bytes_per_pixel = if options.alpha {4} else {3};
+    let stride = (bitmap.width * bytes_per_pixel + 3) & !3;
+    //alpha needs a v4 header to carry the channel masks
+    let info_size: u32 = if options.alpha {108} else {40};
+    let pixel_offset = 14 + info_size;
+    let image_size = (stride * bitmap.height) as u32;
+    let height = if options.top_down {-(bitmap.height as i32)} else {bitmap.height as i32};
+
+    let mut header = Vec::with_capacity(pixel_offset as usize);
+    header.extend_from_slice(b"BM");
+    header.extend_from_slice(&(pixel_offset + image_size).to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&pixel_offset.to_le_bytes());
+
+    header.extend_from_slice(&info_size.to_le_bytes());
+    header.extend_from_slice(&(bitmap.width as i32).to_le_bytes());
+    header.extend_from_slice(&height.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&((bytes_per_pixel * 8) as u16).to_le_bytes());
+    header.extend_from_slice(&(if options.alpha {BI_BITFIELDS} else {BI_RGB}).to_le_bytes());
+    header.extend_from_slice(&image_size.to_le_bytes());
+    //72 dpi, no palette
+    header.extend_from_slice(&2835u32.to_le_bytes());
+    header.extend_from_slice(&2835u32.to_le_bytes());
+    header.extend_from_slice(&[0; 8]);
+    if options.alpha {
+        for mask in &[0xff0000u32, 0xff00, 0xff, 0xff000000] {
+            header.extend_from_slice(&mask.to_le_bytes());
+        }
+        //"sRGB" color space, endpoints and gamma unused
+        header.extend_from_slice(b"BGRs");
+        header.extend_from_slice(&[0; 48]);
+    }
+    f.write_all(&header)?;
+
+    let mut line = vec![0u8; stride];
+    for row in 0..bitmap.height {
+        let y = if options.top_down {row} else {bitmap.height - 1 - row};
+        for (x, color) in bitmap.data[y*bitmap.width..(y+1)*bitmap.width].iter().enumerate() {
+            let (r, g, b, a) = from_rgba_u8(*color);
+            line[x*bytes_per_pixel..(x+1)*bytes_per_pixel].copy_from_slice(&[b, g, r, a][..bytes_per_pixel]);
+        }
+        f.write_all(&line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //3 pixels wide, so 24-bit rows need padding
+    fn bitmap() -> Bitmap {
+        Bitmap {width: 3, height: 2, data: vec![
+            from_u8_rgba(255, 0, 0, 255), from_u8_rgba(0, 255, 0, 128), from_u8_rgba(0, 0, 255, 0),
+            from_u8_rgba(10, 20, 30, 255), from_u8_rgba(40, 50, 60, 1), from_u8_rgba(200, 100, 50, 255)
+        ]}
+    }
+
+    #[test]
+    fn round_trips() {
+        for top_down in [false, true] {
+            let mut file = vec![];
+            write_bmp(&bitmap(), &mut file, BmpOptions {alpha: true, top_down}).unwrap();
+            assert_eq!(decode_bmp(&file).unwrap().data, bitmap().data);
+
+            //without alpha every pixel comes back opaque
+            let mut file = vec![];
+            write_bmp(&bitmap(), &mut file, BmpOptions {alpha: false, top_down}).unwrap();
+            let opaque: Vec<_> = bitmap().data.iter().map(|c| {
+                let (r, g, b, _) = from_rgba_u8(*c);
+                from_u8_rgba(r, g, b, 255)
+            }).collect();
+            assert_eq!(decode_bmp(&file).unwrap().data, opaque);
+        }
+    }
+}
@@ -20,4 +20,9 @@ pub const fn from_rgba_u8(color: u32) -> (u8, u8, u8, u8) {
     )
 }
 
-
+//linear interpolation from `a` (t = 0) to `b` (t = 1), alpha included
+pub fn mix_colors(a: Color, b: Color, t: f64) -> Color {
+    let (a, b) = (from_rgba_u8(a), from_rgba_u8(b));
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    from_u8_rgba(channel(a.0, b.0), channel(a.1, b.1), channel(a.2, b.2), channel(a.3, b.3))
+}
@@ -17,10 +17,10 @@ impl Font {
             match (num, prev) {
 
                 //Ą 
-                (196, 132) => 12*16 + 0,
+                (196, 132) => 12*16,
 
                 //ą 
-                (196, 133) => 14*16 + 0,
+                (196, 133) => 14*16,
 
                 //Ć 
                 (196, 134) => 12*16 + 7,
@@ -75,8 +75,8 @@ impl Font {
 
             }
         };
-        let x = ((num as usize) * self.char_width) % self.bitmap.width;
-        let y = ((num as usize) * self.char_width) / self.bitmap.width;
+        let x = (num * self.char_width) % self.bitmap.width;
+        let y = (num * self.char_width) / self.bitmap.width;
         (x, y*self.char_height)
     }
     
@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//everything that can go wrong while decoding an image file
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    //the file ended before all the pixel data was read
+    Truncated,
+    //image type (or compression method) the decoder doesn't know about
+    UnsupportedType(u32),
+    UnsupportedBitDepth(u32),
+    //the data is there but doesn't make sense, e.g. a run-length packet
+    //running past the end of the image
+    Corrupt(String)
+}
+
+pub type ImageResult<T> = Result<T, ImageError>;
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "i/o error: {}", e),
+            ImageError::Truncated => write!(f, "image data ends unexpectedly"),
+            ImageError::UnsupportedType(t) => write!(f, "unsupported image type {}", t),
+            ImageError::UnsupportedBitDepth(d) => write!(f, "unsupported bit depth {}", d),
+            ImageError::Corrupt(msg) => write!(f, "corrupt image: {}", msg)
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> ImageError {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            ImageError::Truncated
+        } else {
+            ImageError::Io(e)
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::math::{Vector3, Matrix4,
+                  vec3_add, vec3_sub, vec3_mul, vec3_dot, vec3_scale, vec3_normalized, vec3_square_len,
+                  col_mat4_transform};
+use super::colors::{Color, from_u8_rgba, from_rgba_u8};
+
+//lights live in world space, intensities are per channel with 1.0 being
+//full brightness. Point and spot lights don't fall off with distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    //reaches every surface equally
+    Ambient {intensity: Vector3},
+    //infinitely far away, shining along `direction`
+    Directional {direction: Vector3, intensity: Vector3},
+    Point {position: Vector3, intensity: Vector3},
+    //a point light limited to a cone around `direction`; the angles are
+    //measured from its axis in radians, full intensity inside `inner` fading
+    //to nothing at `outer`
+    Spot {position: Vector3, direction: Vector3, intensity: Vector3, inner: f64, outer: f64}
+}
+
+impl Light {
+    //moves the light into the same space as the vertices it gets applied to
+    pub fn transform(&self, matrix: Matrix4) -> Light {
+        let point = |p: Vector3| {
+            let p = col_mat4_transform(matrix, [p[0], p[1], p[2], 1.0]);
+            [p[0], p[1], p[2]]
+        };
+        let vector = |v: Vector3| {
+            let v = col_mat4_transform(matrix, [v[0], v[1], v[2], 0.0]);
+            [v[0], v[1], v[2]]
+        };
+        match *self {
+            Light::Ambient {intensity} => Light::Ambient {intensity},
+            Light::Directional {direction, intensity} =>
+                Light::Directional {direction: vector(direction), intensity},
+            Light::Point {position, intensity} =>
+                Light::Point {position: point(position), intensity},
+            Light::Spot {position, direction, intensity, inner, outer} =>
+                Light::Spot {position: point(position), direction: vector(direction), intensity, inner, outer}
+        }
+    }
+}
+
+//how a surface reacts to the lights
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shading {
+    //ignore the lights and show the surface color as is
+    #[default]
+    Unlit,
+    //one lighting value per triangle, from its face normal
+    Flat,
+    //lighting at the corners, interpolated across the triangle
+    Gouraud,
+    //normals interpolated across the triangle, lighting at every pixel
+    Phong
+}
+
+//the light reaching a point: the surface color gets multiplied by `diffuse`
+//(which includes the ambient light), then `specular` is added on top
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Illumination {
+    pub diffuse: Vector3,
+    pub specular: Vector3
+}
+
+impl Illumination {
+    pub const UNLIT: Illumination = Illumination {diffuse: [1.0; 3], specular: [0.0; 3]};
+
+    pub fn apply(&self, color: Color) -> Color {
+        let (r, g, b, a) = from_rgba_u8(color);
+        let channel = |c: u8, i: usize| {
+            ((c as f64 * self.diffuse[i] + 255.0 * self.specular[i]).clamp(0.0, 255.0)) as u8
+        };
+        from_u8_rgba(channel(r, 0), channel(g, 1), channel(b, 2), a)
+    }
+
+    //weighted sum of three illuminations, e.g. with barycentric weights
+    pub fn blend(values: &[Illumination; 3], weights: [f64; 3]) -> Illumination {
+        let mix = |f: fn(&Illumination) -> Vector3| {
+            (0..3).fold([0.0; 3], |sum, i| vec3_add(sum, vec3_scale(f(&values[i]), weights[i])))
+        };
+        Illumination {
+            diffuse: mix(|i| i.diffuse),
+            specular: mix(|i| i.specular)
+        }
+    }
+}
+
+//the surface properties lighting cares about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    //Ka, multiplies the ambient lights
+    pub ambient: Vector3,
+    //Ks, multiplies the highlights
+    pub specular: Vector3,
+    //Ns, higher is a smaller, sharper highlight; 0 turns highlights off
+    pub shininess: f64
+}
+
+//Phong reflection at `point` with the given `normal`, everything in camera
+//space. The eye sits at the origin, or infinitely far back along
+//`view_direction` if there is one (see Projection::view_direction).
+pub fn illuminate(lights: &[Light], point: Vector3, normal: Vector3, view_direction: Option<Vector3>, surface: &Surface) -> Illumination {
+    //without a direction (e.g. a degenerate face) only the ambient light
+    //reaches the surface
+    if vec3_square_len(normal) == 0.0 {
+        let diffuse = lights.iter().fold([0.0; 3], |sum, light| match *light {
+            Light::Ambient {intensity} => vec3_add(sum, vec3_mul(intensity, surface.ambient)),
+            _ => sum
+        });
+        return Illumination {diffuse, specular: [0.0; 3]};
+    }
+    let normal = vec3_normalized(normal);
+    let to_eye = vec3_normalized(vec3_scale(view_direction.unwrap_or(point), -1.0));
+    let mut diffuse = [0.0; 3];
+    let mut specular = [0.0; 3];
+
+    for light in lights {
+        //direction towards the light, and how much of it gets here
+        let (to_light, intensity) = match *light {
+            Light::Ambient {intensity} => {
+                diffuse = vec3_add(diffuse, vec3_mul(intensity, surface.ambient));
+                continue;
+            },
+            Light::Directional {direction, intensity} =>
+                (vec3_normalized(vec3_scale(direction, -1.0)), intensity),
+            Light::Point {position, intensity} =>
+                (vec3_normalized(vec3_sub(position, point)), intensity),
+            Light::Spot {position, direction, intensity, inner, outer} => {
+                let to_light = vec3_normalized(vec3_sub(position, point));
+                //rounding can push the cosine just past 1 right on the axis
+                let cos = vec3_dot(vec3_scale(to_light, -1.0), vec3_normalized(direction));
+                let angle = cos.clamp(-1.0, 1.0).acos();
+                let cone = if outer > inner {
+                    ((outer - angle) / (outer - inner)).clamp(0.0, 1.0)
+                } else if angle <= outer {1.0} else {0.0};
+                (to_light, vec3_scale(intensity, cone))
+            }
+        };
+
+        let n_dot_l = vec3_dot(normal, to_light);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        diffuse = vec3_add(diffuse, vec3_scale(intensity, n_dot_l));
+
+        if surface.shininess > 0.0 {
+            let reflected = vec3_sub(vec3_scale(normal, 2.0 * n_dot_l), to_light);
+            let r_dot_v = vec3_dot(reflected, to_eye);
+            if r_dot_v > 0.0 {
+                let highlight = vec3_scale(intensity, r_dot_v.powf(surface.shininess));
+                specular = vec3_add(specular, vec3_mul(highlight, surface.specular));
+            }
+        }
+    }
+
+    Illumination {diffuse, specular}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_view_sees_highlight_from_one_direction() {
+        //a light shining straight down the view direction, off to the side
+        //of the eye; the reflection points straight back at the camera
+        let lights = [Light::Directional {direction: [0.0, 0.0, 1.0], intensity: [1.0; 3]}];
+        let surface = Surface {ambient: [1.0; 3], specular: [1.0; 3], shininess: 50.0};
+        let point = [4.0, 0.0, 5.0];
+        let normal = [0.0, 0.0, -1.0];
+
+        let parallel = illuminate(&lights, point, normal, Some([0.0, 0.0, 1.0]), &surface);
+        assert_eq!(parallel.specular, [1.0; 3]);
+        //seen from the origin it's at an angle, so the highlight fades
+        let perspective = illuminate(&lights, point, normal, None, &surface);
+        assert!(perspective.specular[0] < 0.01);
+    }
+}
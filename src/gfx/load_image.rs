@@ -0,0 +1,34 @@
+use std::fs;
+use std::path::Path;
+
+use super::bitmaps::{Bitmap};
+use super::image_error::{ImageError, ImageResult};
+use super::load_tga::{decode_tga};
+use super::png::{decode_png};
+use super::bmp::{decode_bmp};
+use super::netpbm::{decode_netpbm};
+
+//loads any of the supported image formats, going by the file's contents
+//where the format has a signature and by the extension otherwise
+pub fn load_bitmap<P: AsRef<Path>>(filename: P) -> ImageResult<Bitmap> {
+    let path = filename.as_ref();
+    let file = fs::read(path)?;
+    if file.starts_with(&[137, 80, 78, 71]) {
+        return decode_png(&file);
+    }
+    if file.starts_with(b"BM") {
+        return decode_bmp(&file);
+    }
+    if file.len() > 2 && file[0] == b'P' && (b'1'..=b'7').contains(&file[1])
+        && file[2].is_ascii_whitespace() {
+        return decode_netpbm(&file);
+    }
+
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("tga") => decode_tga(&file),
+        _ => Err(ImageError::Corrupt(format!("can't tell the image format of {}", path.display())))
+    }
+}
@@ -1,104 +1,281 @@
 use std::io;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs;
 use std::path::Path;
 
 use super::colors::{Color, from_u8_rgb, from_u8_rgba};
 use super::bitmaps::{Bitmap};
+use super::image_error::{ImageError, ImageResult};
 
-pub fn load_bitmap_from_tga<P: AsRef<Path>>(filename: P) -> io::Result<Bitmap> {
-    let mut f = File::open(filename)?;
+//how the bytes of a single pixel should be turned into a color
+enum PixelKind {
+    //types 1 and 9: the pixel is an index into the color map
+    ColorMapped(Vec<Color>),
+    //types 2 and 10
+    TrueColor,
+    //types 3 and 11
+    Grayscale
+}
+
+//15/16-bit pixels are stored as ARGB1555, the rest as bgr(a)
+fn decode_true_color(bytes: &[u8], alpha_bits: u8) -> Color {
+    match bytes.len() {
+        2 => {
+            let value = (bytes[1] as u16) << 8 | bytes[0] as u16;
+            let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+            let (r, g, b) = (
+                expand((value >> 10) & 31),
+                expand((value >> 5) & 31),
+                expand(value & 31)
+            );
+            //the attribute bit is only meaningful if the descriptor says so,
+            //plenty of exporters leave it at zero for opaque images
+            if alpha_bits > 0 && value >> 15 == 0 {
+                from_u8_rgba(r, g, b, 0)
+            } else {
+                from_u8_rgb(r, g, b)
+            }
+        },
+        3 => from_u8_rgb(bytes[2], bytes[1], bytes[0]),
+        //same as above, the fourth byte is only alpha if the descriptor
+        //says the image has any
+        _ if alpha_bits > 0 => from_u8_rgba(bytes[2], bytes[1], bytes[0], bytes[3]),
+        _ => from_u8_rgb(bytes[2], bytes[1], bytes[0])
+    }
+}
+
+fn decode_pixel(kind: &PixelKind, bytes: &[u8], alpha_bits: u8) -> ImageResult<Color> {
+    match kind {
+        PixelKind::ColorMapped(palette) => {
+            let index = if bytes.len() == 1 {
+                bytes[0] as usize
+            } else {
+                (bytes[1] as usize) << 8 | bytes[0] as usize
+            };
+            palette.get(index).copied().ok_or_else(|| ImageError::Corrupt(
+                format!("color map index {} out of range", index)
+            ))
+        },
+        PixelKind::TrueColor => Ok(decode_true_color(bytes, alpha_bits)),
+        PixelKind::Grayscale => Ok(if bytes.len() == 1 {
+            from_u8_rgb(bytes[0], bytes[0], bytes[0])
+        } else {
+            //16-bit grayscale carries an alpha byte
+            from_u8_rgba(bytes[0], bytes[0], bytes[0], bytes[1])
+        })
+    }
+}
+
+pub fn load_bitmap_from_tga<P: AsRef<Path>>(filename: P) -> ImageResult<Bitmap> {
+    decode_tga(&fs::read(filename)?)
+}
+
+pub fn decode_tga(file: &[u8]) -> ImageResult<Bitmap> {
+    let mut f = file;
     let mut header = vec![0u8; 18];
     f.read_exact(&mut header)?;
 
+    let id_length = header[0] as u64;
+    let color_map_type = header[1];
+    let image_type = header[2];
+    let color_map_first = ((header[4] as u16) << 8 | header[3] as u16) as usize;
+    let color_map_length = ((header[6] as u16) << 8 | header[5] as u16) as usize;
+    let color_map_bpp = (header[7] as usize + 7) >> 3;
     let width = ((header[13] as u16) << 8 | header[12] as u16) as usize;
     let height = ((header[15] as u16) << 8 | header[14] as u16) as usize;
-    let bpp = header[16] as usize >> 3;
-    let image_type = header[2];
+    let bpp = (header[16] as usize + 7) >> 3;
+    let kind_id = image_type & 7;
+    let alpha_bits = header[17] & 15;
+
+    if !matches!(image_type, 1 | 2 | 3 | 9 | 10 | 11) {
+        return Err(ImageError::UnsupportedType(image_type as u32));
+    }
+    let depth_ok = match kind_id {
+        1 => matches!(header[16], 8 | 16),
+        2 => matches!(header[16], 15 | 16 | 24 | 32),
+        _ => matches!(header[16], 8 | 16)
+    };
+    if !depth_ok {
+        return Err(ImageError::UnsupportedBitDepth(header[16] as u32));
+    }
+    if color_map_type == 1 && !matches!(header[7], 15 | 16 | 24 | 32) {
+        return Err(ImageError::UnsupportedBitDepth(header[7] as u32));
+    }
+    if kind_id == 1 && color_map_type != 1 {
+        return Err(ImageError::Corrupt("color-mapped image without a color map".to_string()));
+    }
+
+    //skip the image ID field
+    io::copy(&mut (&mut f).take(id_length), &mut io::sink())?;
+
+    //the color map is present (and has to be skipped) even for images that
+    //don't use it
+    let mut palette = Vec::new();
+    if color_map_type == 1 {
+        let mut entry = vec![0; color_map_bpp];
+        palette = vec![0u32; color_map_first];
+        for _ in 0..color_map_length {
+            f.read_exact(&mut entry)?;
+            palette.push(decode_true_color(&entry, alpha_bits));
+        }
+    }
+
+    let kind = match kind_id {
+        1 => PixelKind::ColorMapped(palette),
+        2 => PixelKind::TrueColor,
+        _ => PixelKind::Grayscale
+    };
+
+    //every pixel takes at least bpp bytes, or a packet header and bpp
+    //bytes for every 128 of them when run-length encoded; anything bigger
+    //than what's left of the file is corrupt, and mustn't get allocated
+    let pixels = width.checked_mul(height)
+        .filter(|p| {
+            let needed = if image_type & 8 == 0 {
+                p.checked_mul(bpp)
+            } else {
+                p.div_ceil(128).checked_mul(1 + bpp)
+            };
+            needed.is_some_and(|needed| needed <= f.len())
+        })
+        .ok_or_else(|| ImageError::Corrupt(format!("{}x{} pixels don't fit in the file", width, height)))?;
 
-    let mut data = vec![0u32; width*height];
-    if image_type == 2 {
-        
-        let mut pixel_data = vec![0; bpp];
+    let mut data = vec![0u32; pixels];
+    let mut pixel_data = vec![0; bpp];
+    if image_type & 8 == 0 {
         for item in &mut data {
             f.read_exact(&mut pixel_data)?;
-            *item = from_u8_rgb(
-                pixel_data[2],
-                pixel_data[1],
-                pixel_data[0]
-            );
+            *item = decode_pixel(&kind, &pixel_data, alpha_bits)?;
         }
-        
-            
-        
-    } else if image_type == 10 {
+    } else {
         let mut index = 0;
-        while index < width*height {
+        while index < pixels {
             let mut datapiece = [0;1];
             f.read_exact(&mut datapiece)?;
             let packet = datapiece[0];
+            let count = (packet & 127) as usize + 1;
+            if index + count > pixels {
+                return Err(ImageError::Corrupt(
+                    format!("run-length packet of {} pixels overflows the image at pixel {}", count, index)
+                ));
+            }
             if packet >> 7 == 1 {
-                let repetitions = packet - 128 + 1;
-                let mut pixel_colors = vec![0; bpp];
-                f.read_exact(&mut pixel_colors)?;
-                for _ in 0..repetitions{
-                    if bpp == 3 {
-                        data[index] = from_u8_rgb(
-                            pixel_colors[2],
-                            pixel_colors[1],
-                            pixel_colors[0]
-                        );
-                    } else {
-                        //has alpha channel
-                        data[index] = from_u8_rgba(
-                            pixel_colors[2],
-                            pixel_colors[1],
-                            pixel_colors[0],
-                            pixel_colors[3],
-                        );
-                    }
-                    index += 1;
+                f.read_exact(&mut pixel_data)?;
+                let color = decode_pixel(&kind, &pixel_data, alpha_bits)?;
+                for item in &mut data[index..index+count] {
+                    *item = color;
                 }
+                index += count;
             } else {
-                let mut pixel_data = vec![0; bpp];
-                for _ in 0..(packet + 1) {
+                for item in &mut data[index..index+count] {
                     f.read_exact(&mut pixel_data)?;
-                    if bpp == 3 {
-                        data[index] = from_u8_rgb(
-                            pixel_data[2],
-                            pixel_data[1],
-                            pixel_data[0]
-                        );
-                    } else {
-                        //has alpha channel
-                        data[index] = from_u8_rgba(
-                            pixel_data[2],
-                            pixel_data[1],
-                            pixel_data[0],
-                            pixel_data[3],
-                        );
-                    }
-                    index += 1;
+                    *item = decode_pixel(&kind, &pixel_data, alpha_bits)?;
                 }
-
-                
+                index += count;
             }
         }
-    } else {
-        unimplemented!()
     }
-    if header[17] >> 4 == 0 {
-        for h in 0..height{
-            let mut line = &mut data[h*width..(h+1)*width];
+
+    //bit 4 of the descriptor: pixels go right-to-left
+    if header[17] & (1 << 4) != 0 {
+        for line in data.chunks_mut(width.max(1)) {
             line.reverse();
         }
-        data.reverse();
-
-        
+    }
+    //bit 5: rows go top-to-bottom, otherwise the first row is the bottom one
+    if header[17] & (1 << 5) == 0 {
+        for h in 0..height/2 {
+            let (top, bottom) = data.split_at_mut((height-1-h)*width);
+            top[h*width..(h+1)*width].swap_with_slice(&mut bottom[..width]);
+        }
     }
 
-    
-    
     Ok(Bitmap {width, height, data})
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::save_tga::{write_tga, TgaOptions, TgaCompression, TgaOrigin};
+
+    //an 18 byte header with nothing after it
+    fn header(image_type: u8, width: u16, height: u16, bits: u8, descriptor: u8) -> Vec<u8> {
+        let mut header = vec![0; 18];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = bits;
+        header[17] = descriptor;
+        header
+    }
+
+    #[test]
+    fn rejects_size_larger_than_file() {
+        for image_type in [2, 10] {
+            let mut file = header(image_type, 65535, 65535, 32, 8);
+            file.extend([0; 64]);
+            assert!(matches!(decode_tga(&file), Err(ImageError::Corrupt(_))));
+        }
+    }
+
+    #[test]
+    fn reads_alpha_only_with_alpha_bits() {
+        let pixel = [30, 20, 10, 77];
+        let mut with_alpha = header(2, 1, 1, 32, 8);
+        with_alpha.extend(pixel);
+        assert_eq!(decode_tga(&with_alpha).unwrap().data, vec![from_u8_rgba(10, 20, 30, 77)]);
+
+        let mut without_alpha = header(2, 1, 1, 32, 0);
+        without_alpha.extend(pixel);
+        assert_eq!(decode_tga(&without_alpha).unwrap().data, vec![from_u8_rgb(10, 20, 30)]);
+    }
+
+    #[test]
+    fn round_trips() {
+        //a run long enough for an rle packet next to pixels that differ
+        let bitmap = Bitmap {width: 4, height: 3, data: vec![
+            from_u8_rgba(255, 0, 0, 255), from_u8_rgba(255, 0, 0, 255), from_u8_rgba(255, 0, 0, 255), from_u8_rgba(0, 255, 0, 128),
+            from_u8_rgba(0, 0, 255, 0), from_u8_rgba(10, 20, 30, 255), from_u8_rgba(40, 50, 60, 255), from_u8_rgba(40, 50, 60, 255),
+            from_u8_rgba(1, 2, 3, 4), from_u8_rgba(5, 6, 7, 8), from_u8_rgba(9, 10, 11, 12), from_u8_rgba(200, 100, 50, 255)
+        ]};
+        for compression in [TgaCompression::Uncompressed, TgaCompression::Rle] {
+            for origin in [TgaOrigin::TopLeft, TgaOrigin::BottomLeft] {
+                let mut file = vec![];
+                write_tga(&bitmap, &mut file, TgaOptions {compression, alpha: true, origin}).unwrap();
+                assert_eq!(file[2], if compression == TgaCompression::Rle {10} else {2});
+                let decoded = decode_tga(&file).unwrap();
+                assert_eq!((decoded.width, decoded.height), (4, 3));
+                assert_eq!(decoded.data, bitmap.data, "{:?} {:?}", compression, origin);
+            }
+        }
+    }
+
+    //two pixels, red and blue, as indices into a 24-bit color map
+    fn color_mapped(image_type: u8, pixels: &[u8]) -> Vec<u8> {
+        let mut file = header(image_type, 2, 1, 8, 32);
+        file[1] = 1;
+        file[5] = 2;
+        file[7] = 24;
+        file.extend([0, 0, 255, 255, 0, 0]);
+        file.extend(pixels);
+        file
+    }
+
+    #[test]
+    fn decodes_color_mapped_and_grayscale() {
+        let (red, blue) = (from_u8_rgb(255, 0, 0), from_u8_rgb(0, 0, 255));
+        assert_eq!(decode_tga(&color_mapped(1, &[1, 0])).unwrap().data, vec![blue, red]);
+        //one run of two
+        assert_eq!(decode_tga(&color_mapped(9, &[129, 1])).unwrap().data, vec![blue, blue]);
+
+        let gray = vec![from_u8_rgb(10, 10, 10), from_u8_rgb(200, 200, 200)];
+        let mut file = header(3, 2, 1, 8, 32);
+        file.extend([10, 200]);
+        assert_eq!(decode_tga(&file).unwrap().data, gray);
+        //one raw packet of two
+        let mut file = header(11, 2, 1, 8, 32);
+        file.extend([1, 10, 200]);
+        assert_eq!(decode_tga(&file).unwrap().data, gray);
+    }
+}
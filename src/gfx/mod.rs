@@ -1,8 +1,20 @@
 pub mod colors;
 pub mod bitmaps;
+pub mod image_error;
 pub mod load_tga;
+pub mod save_tga;
+pub mod zlib;
+pub mod png;
+pub mod bmp;
+pub mod netpbm;
+pub mod load_image;
 pub mod primitives;
 pub mod font;
+pub mod sampler;
+pub mod projection;
+pub mod lighting;
 pub mod render_2d;
 pub mod render_3d;
+pub mod shaders;
+pub mod render_target;
 pub mod model_loading;
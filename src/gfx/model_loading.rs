@@ -1,116 +1,614 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::SplitAsciiWhitespace;
+
 use crate::math::{Vector4, Vector3};
-use super::render_3d::{Model, MaterialData, PolygonData};
+use super::render_3d::{Model, PolygonData, Material, MaterialGroup, SmoothingGroup, BlendMode};
+use super::load_image::load_bitmap;
+use super::sampler::Wrap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjErrorKind {
+    //a statement with fewer values than it needs, e.g. "v 1.0 2.0"
+    MissingValues { keyword: String, expected: &'static str },
+    BadNumber { keyword: String, value: String },
+    //an index of 0 or one pointing past the elements defined so far
+    BadIndex { keyword: &'static str, index: String },
+    //a material library or texture referenced at this line couldn't be read
+    MissingFile { path: PathBuf, reason: String }
+}
+
+//line numbers start at 1, like in any text editor; `file` is only known
+//when loading from a path, and points at the .mtl file for errors in one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub kind: ObjErrorKind
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ObjErrorKind::MissingValues {keyword, expected} =>
+                write!(f, "insufficient number of elements in field {} (expected {})", keyword, expected),
+            ObjErrorKind::BadNumber {keyword, value} =>
+                write!(f, "wrong element format in field {}: {:?} is not a number", keyword, value),
+            ObjErrorKind::BadIndex {keyword, index} =>
+                write!(f, "{} index {} doesn't refer to a defined element", keyword, index),
+            ObjErrorKind::MissingFile {path, reason} =>
+                write!(f, "can't load {}: {}", path.display(), reason)
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+struct Statement<'line> {
+    line: usize,
+    keyword: &'line str,
+    values: SplitAsciiWhitespace<'line>
+}
+
+impl<'line> Statement<'line> {
+    fn error(&self, kind: ObjErrorKind) -> ObjError {
+        ObjError {file: None, line: self.line, kind}
+    }
 
+    fn parse(&self, value: &str) -> Result<f64, ObjError> {
+        value.parse().map_err(|_| self.error(ObjErrorKind::BadNumber {
+            keyword: self.keyword.to_string(),
+            value: value.to_string()
+        }))
+    }
+
+    //reads `required` numbers followed by as many optional ones as there are
+    fn numbers(&self, required: usize, expected: &'static str) -> Result<Vec<f64>, ObjError> {
+        let values = self.values.clone()
+            .map(|v| self.parse(v))
+            .collect::<Result<Vec<f64>, ObjError>>()?;
+        if values.len() < required {
+            return Err(self.error(ObjErrorKind::MissingValues {
+                keyword: self.keyword.to_string(),
+                expected
+            }));
+        }
+        Ok(values)
+    }
+
+    //turns a 1-based (or negative, relative to the end) obj index into a
+    //0-based one into a list that currently holds `count` elements
+    fn index(&self, keyword: &'static str, value: &str, count: usize) -> Result<usize, ObjError> {
+        let bad = || self.error(ObjErrorKind::BadIndex {keyword, index: value.to_string()});
+        let index: isize = value.parse().map_err(|_| bad())?;
+        let resolved = if index > 0 {
+            index - 1
+        } else {
+            count as isize + index
+        };
+        if index == 0 || resolved < 0 || resolved >= count as isize {
+            return Err(bad());
+        }
+        Ok(resolved as usize)
+    }
+}
+
+//2D cross product of the edges (a, b) and (b, c)
+fn turn(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - b[1]) - (b[1] - a[1]) * (c[0] - b[0])
+}
+
+fn inside_triangle(p: [f64; 2], a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> bool {
+    turn(a, b, p) >= 0.0 && turn(b, c, p) >= 0.0 && turn(c, a, p) >= 0.0
+}
+
+//splits a planar polygon into triangles, returned as triples of positions
+//in `corners`. Convex polygons become a fan around the first corner,
+//concave ones go through ear clipping.
+pub fn triangulate(corners: &[Vector4]) -> Vec<[usize; 3]> {
+    let n = corners.len();
+    if n < 3 {
+        return Vec::new();
+    }
+
+    //polygon normal (Newell's method), then drop its largest axis to get a
+    //2D polygon that winds counter-clockwise
+    let mut normal = [0.0; 3];
+    for i in 0..n {
+        let (a, b) = (corners[i], corners[(i + 1) % n]);
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    let axis = (0..3).fold(0, |best, i| if normal[i].abs() > normal[best].abs() {i} else {best});
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let sign = if normal[axis] < 0.0 {-1.0} else {1.0};
+    let points: Vec<[f64; 2]> = corners.iter().map(|c| [c[u], c[v] * sign]).collect();
+
+    let convex = (0..n).all(|i| turn(points[i], points[(i + 1) % n], points[(i + 2) % n]) >= 0.0);
+    if convex {
+        return (1..n-1).map(|i| [0, i, i + 1]).collect();
+    }
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            turn(points[a], points[b], points[c]) > 0.0
+                && remaining.iter()
+                    .filter(|&&p| p != a && p != b && p != c)
+                    .all(|&p| !inside_triangle(points[p], points[a], points[b], points[c]))
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            },
+            //self-intersecting or degenerate, fan out whatever is left
+            None => break
+        }
+    }
+    for i in 1..remaining.len()-1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+//comments can also follow a statement on the same line
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(start) => &line[..start],
+        None => line
+    }
+}
+
+pub fn load_obj_file(file: &str) -> Result<Model, ObjError> {
+    let lines = file.lines();
 
-pub fn load_obj_file(file: &str) -> Model{
-    let mut lines = file.lines();
-    
     let mut vertices : Vec<Vector4> = Vec::new();
     let mut vertex_normals: Vec<Vector3> = Vec::new();
     let mut v_texture_coords: Vec<Vector3> = Vec::new();
 
     let mut triangles: Vec<PolygonData> = Vec::new();
-    
+
+    let mut materials: Vec<Material> = Vec::new();
+    let mut material_groups: Vec<MaterialGroup> = Vec::new();
+    let mut smoothing_groups: Vec<SmoothingGroup> = Vec::new();
+
     for (num, line) in lines.enumerate() {
-        let mut split = line.split_ascii_whitespace();        
-        if let Some(first) = split.next() {
-            match first {
-                "#" => continue,
-                "mtllib" => continue,
-                "o" => continue,
-                "usemtl" => continue,
-                "s" => continue,
-                "v" => {
-                    let er_num = &format!("Insufficient number of elements in field v at line {} (expected 3 or 4)", num);
-                    let er_kind = &format!("Wrong element format in field v at line {} (expected float)", num);
-                    
-                    let new_vertex: Vector4 = [
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().unwrap_or("1.0")
-                            .parse().unwrap()
-                    ]; 
-                    vertices.push(new_vertex);
-                },
-                
-                "vn" => {
-                     let er_num = &format!("Insufficient number of elements in field vn at line {} (expected 3)", num);
-                    let er_kind = &format!("Wrong element format in field vn at line {} (expected float)", num);
-                    
-                    let new_normal: Vector3 = [
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                    ];
-                    vertex_normals.push(new_normal);
-                },
-                
-                
-                "vt" => {
-                    
-                     let er_num = &format!("Insufficient number of elements in field vt at line {} (expected 2 or 3)", num);
-                    let er_kind = &format!("Wrong element format in field vt at line {} (expected float)", num);
-                    
-                    let new_texture: Vector3 = [
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().expect(er_num)
-                            .parse().expect(er_kind),
-                        split.next().unwrap_or("0.0")
-                            .parse().unwrap()
-                    ];
-                    
-                    v_texture_coords.push(new_texture);
-                },
-
-                "f" => {
-                    let er_kind = format!("Wrong element format in field vt at line {} (expected integer)", num);
-                    
-                    let mut vertex = [0, 0, 0];
-                    let mut normal = [0, 0, 0];
-                    let mut uv_coord = [0, 0, 0];
-                    for (i, elem) in split.enumerate() {
-                        if i > 2 {
-                            panic!("Too many verticies found in face at line {} - only triangles supported!", num)
-                        }
-                        for (value, kind) in elem.split("/").zip([&mut vertex, &mut uv_coord, &mut normal]) {
-                            if value != "" { 
-                                let v : usize = value.parse().expect(&er_kind);
-                                kind[i] = v -1;
-                            }
-                        }
-                        
+        let mut split = strip_comment(line).split_ascii_whitespace();
+        let first = match split.next() {
+            Some(first) => first,
+            None => continue
+        };
+        let statement = Statement {line: num + 1, keyword: first, values: split};
+
+        match first {
+            "v" => {
+                //x y z [w], or x y z r g b as some exporters write vertex colors
+                let v = statement.numbers(3, "3 or 4")?;
+                let w = if v.len() == 4 {v[3]} else {1.0};
+                vertices.push([v[0], v[1], v[2], w]);
+            },
+
+            "vn" => {
+                let v = statement.numbers(3, "3")?;
+                vertex_normals.push([v[0], v[1], v[2]]);
+            },
+
+            "vt" => {
+                let v = statement.numbers(1, "1 to 3")?;
+                v_texture_coords.push([
+                    v[0],
+                    v.get(1).copied().unwrap_or(0.0),
+                    v.get(2).copied().unwrap_or(0.0)
+                ]);
+            },
+
+            "f" => {
+                let corners: Vec<&str> = statement.values.clone().collect();
+                if corners.len() < 3 {
+                    return Err(statement.error(ObjErrorKind::MissingValues {
+                        keyword: "f".to_string(),
+                        expected: "at least 3"
+                    }));
+                }
+
+                //(vertex, uv, normal) of each corner
+                let mut indices = Vec::with_capacity(corners.len());
+                for elem in &corners {
+                    //v, v/vt, v//vn or v/vt/vn
+                    let mut parts = elem.split('/');
+                    let vertex = statement.index("v", parts.next().unwrap_or(""), vertices.len())?;
+                    let mut uv_coord = None;
+                    let mut normal = None;
+                    if let Some(value) = parts.next().filter(|v| !v.is_empty()) {
+                        uv_coord = Some(statement.index("vt", value, v_texture_coords.len())?);
                     }
-                    
+                    if let Some(value) = parts.next().filter(|v| !v.is_empty()) {
+                        normal = Some(statement.index("vn", value, vertex_normals.len())?);
+                    }
+                    indices.push((vertex, uv_coord, normal));
+                }
+
+                let positions: Vec<Vector4> = indices.iter().map(|c| vertices[c.0]).collect();
+                for [a, b, c] in triangulate(&positions) {
+                    let (a, b, c) = (indices[a], indices[b], indices[c]);
+                    triangles.push(PolygonData {
+                        vertex: [a.0, b.0, c.0],
+                        normal: [a.2, b.2, c.2],
+                        uv_coord: [a.1, b.1, c.1]
+                    });
+                }
+            },
 
-                    triangles.push(
-                        PolygonData{vertex, normal, uv_coord}
-                    );
-                    
-                    
-                },
+            //the materials only get a name here, load_obj_from_path fills in
+            //the rest from the material libraries
+            "usemtl" => {
+                let name = statement.values.clone().next().unwrap_or("");
+                let material = match materials.iter().position(|m| m.name == name) {
+                    Some(index) => index,
+                    None => {
+                        materials.push(Material::new(name));
+                        materials.len() - 1
+                    }
+                };
+                //close the previous group, dropping it if it got no faces
+                if let Some(group) = material_groups.last_mut() {
+                    group.triangles.end = triangles.len();
+                    if group.triangles.is_empty() {
+                        material_groups.pop();
+                    }
+                }
+                material_groups.push(MaterialGroup {
+                    material,
+                    triangles: triangles.len()..triangles.len()
+                });
+            },
 
+            "s" => {
+                let value = statement.values.clone().next().unwrap_or("off");
+                let group = if value == "off" {
+                    0
+                } else {
+                    value.parse().map_err(|_| statement.error(ObjErrorKind::BadNumber {
+                        keyword: "s".to_string(),
+                        value: value.to_string()
+                    }))?
+                };
+                if let Some(last) = smoothing_groups.last_mut() {
+                    last.triangles.end = triangles.len();
+                    if last.triangles.is_empty() {
+                        smoothing_groups.pop();
+                    }
+                }
+                smoothing_groups.push(SmoothingGroup {
+                    group,
+                    triangles: triangles.len()..triangles.len()
+                });
+            },
 
-                _ => panic!("Unexpected element found at line {}", num)
+            //grouping, lines, points, free-form curves and
+            //surfaces, and anything else we don't draw
+            _ => continue
+        }
+    }
 
-            }
+    if let Some(group) = material_groups.last_mut() {
+        group.triangles.end = triangles.len();
+        if group.triangles.is_empty() {
+            material_groups.pop();
+        }
+    }
 
+    if let Some(last) = smoothing_groups.last_mut() {
+        last.triangles.end = triangles.len();
+        if last.triangles.is_empty() {
+            smoothing_groups.pop();
         }
-        
     }
 
-    Model {
+    Ok(Model {
         vertices,
         uv_map: v_texture_coords,
-        vertex_normals: vertex_normals,
-        triangles
+        vertex_normals,
+        triangles,
+        materials,
+        material_groups,
+        smoothing_groups,
+        tangents: vec![]
+    })
+}
+
+//a texture map statement, remembered with its line for error messages
+#[derive(Debug, Clone)]
+pub struct MtlTexture {
+    pub file: String,
+    pub line: usize
+}
+
+//an MTL file's materials, with texture maps left as file names
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub material: Material,
+    pub diffuse_map: Option<MtlTexture>,
+    pub alpha_map: Option<MtlTexture>,
+    pub specular_map: Option<MtlTexture>,
+    pub normal_map: Option<MtlTexture>
+}
+
+pub fn parse_mtl_file(file: &str) -> Result<Vec<MtlMaterial>, ObjError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (num, line) in file.lines().enumerate() {
+        let mut split = strip_comment(line).split_ascii_whitespace();
+        let first = match split.next() {
+            Some(first) => first,
+            None => continue
+        };
+        let statement = Statement {line: num + 1, keyword: first, values: split};
+
+        if first == "newmtl" {
+            let name = statement.values.clone().next().unwrap_or("");
+            materials.push(MtlMaterial {
+                material: Material::new(name),
+                diffuse_map: None,
+                alpha_map: None,
+                specular_map: None,
+                normal_map: None
+            });
+            continue;
+        }
+        //anything before the first newmtl has nothing to apply to
+        let current = match materials.last_mut() {
+            Some(current) => current,
+            None => continue
+        };
+
+        //"Kd r [g b]", a single value means gray; the "spectral" and "xyz"
+        //forms aren't supported and leave the default
+        let color = || -> Result<Option<Vector3>, ObjError> {
+            if matches!(statement.values.clone().next(), Some("spectral") | Some("xyz")) {
+                return Ok(None);
+            }
+            let v = statement.numbers(1, "1 or 3")?;
+            Ok(Some(if v.len() >= 3 {[v[0], v[1], v[2]]} else {[v[0], v[0], v[0]]}))
+        };
+        //texture statements can carry options like "-s 1 1 1" before the
+        //file name, which we mostly don't support, so only the last value
+        //counts
+        let map = || statement.values.clone().last().map(|name| MtlTexture {
+            file: name.to_string(),
+            line: statement.line
+        });
+        //"-clamp on" stops the map from repeating outside 0 to 1
+        let clamped = || {
+            let values: Vec<&str> = statement.values.clone().collect();
+            values.windows(2).any(|pair| pair == ["-clamp", "on"])
+        };
+
+        match first {
+            "Ka" => if let Some(c) = color()? {current.material.ambient = c},
+            "Kd" => if let Some(c) = color()? {current.material.diffuse = c},
+            "Ks" => if let Some(c) = color()? {current.material.specular = c},
+            "Ns" => current.material.shininess = statement.numbers(1, "1")?[0],
+            "d" => {
+                //"d -halo 0.5" is treated like a plain dissolve
+                let value = statement.values.clone().last().unwrap_or("");
+                current.material.opacity = statement.parse(value)?;
+            },
+            "Tr" => current.material.opacity = 1.0 - statement.numbers(1, "1")?[0],
+            "map_Kd" => {
+                current.diffuse_map = map();
+                if clamped() {
+                    current.material.sampler.wrap_u = Wrap::ClampToEdge;
+                    current.material.sampler.wrap_v = Wrap::ClampToEdge;
+                }
+            },
+            "map_d" => current.alpha_map = map(),
+            "map_Ks" => current.specular_map = map(),
+            //bump maps are taken to be tangent space normal maps, which is
+            //what most exporters put there
+            "norm" | "map_Bump" | "map_bump" | "bump" => current.normal_map = map(),
+            //illum, Ni, Ke, Tf, the other maps...
+            _ => continue
+        }
+    }
+
+    //see-through materials need blending to show it
+    for definition in &mut materials {
+        if definition.material.opacity < 1.0 || definition.alpha_map.is_some() {
+            definition.material.blend_mode = BlendMode::AlphaBlend;
+        }
+    }
+
+    Ok(materials)
+}
+
+fn read_file(path: &Path, from: Option<&Path>, line: usize) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|e| ObjError {
+        file: from.map(|f| f.to_path_buf()),
+        line,
+        kind: ObjErrorKind::MissingFile {path: path.to_path_buf(), reason: e.to_string()}
+    })
+}
+
+//loads an OBJ file along with the material libraries it references and
+//their textures, all looked up relative to the file that mentions them
+pub fn load_obj_from_path<P: AsRef<Path>>(filename: P) -> Result<Model, ObjError> {
+    let obj_path = filename.as_ref();
+    let directory = obj_path.parent().unwrap_or_else(|| Path::new(""));
+    let file = read_file(obj_path, None, 0)?;
+    let with_file = |e: ObjError, path: &Path| ObjError {file: e.file.or_else(|| Some(path.to_path_buf())), ..e};
+
+    let mut model = load_obj_file(&file).map_err(|e| with_file(e, obj_path))?;
+
+    for (num, line) in file.lines().enumerate() {
+        let mut split = strip_comment(line).split_ascii_whitespace();
+        if split.next() != Some("mtllib") {
+            continue;
+        }
+        //one statement can list several libraries
+        for library in split {
+            let mtl_path = directory.join(library);
+            let mtl = read_file(&mtl_path, Some(obj_path), num + 1)?;
+            let mtl_directory = mtl_path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+            for definition in parse_mtl_file(&mtl).map_err(|e| with_file(e, &mtl_path))? {
+                let material = match model.materials.iter_mut()
+                    .find(|m| m.name == definition.material.name) {
+                        Some(material) => material,
+                        None => continue
+                    };
+                *material = definition.material;
+
+                let texture = |map: &MtlTexture| -> Result<_, ObjError> {
+                    let path = mtl_directory.join(&map.file);
+                    load_bitmap(&path).map_err(|e| ObjError {
+                        file: Some(mtl_path.clone()),
+                        line: map.line,
+                        kind: ObjErrorKind::MissingFile {path: path.clone(), reason: e.to_string()}
+                    })
+                };
+                if let Some(map) = &definition.diffuse_map {
+                    material.diffuse_map = Some(texture(map)?);
+                }
+                if let Some(map) = &definition.alpha_map {
+                    material.alpha_map = Some(texture(map)?);
+                }
+                if let Some(map) = &definition.specular_map {
+                    material.specular_map = Some(texture(map)?);
+                }
+                if let Some(map) = &definition.normal_map {
+                    material.normal_map = Some(texture(map)?);
+                }
+            }
+        }
+    }
+
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //an L made of three unit squares, concave at (1, 1)
+    const L_SHAPE: [[f64; 2]; 6] = [[0.0, 0.0], [2.0, 0.0], [2.0, 1.0], [1.0, 1.0], [1.0, 2.0], [0.0, 2.0]];
+
+    fn signed_area(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+        turn(a, b, c) / 2.0
+    }
+
+    #[test]
+    fn triangulates_concave_face_in_both_windings() {
+        for reversed in [false, true] {
+            let mut points = L_SHAPE.to_vec();
+            if reversed {
+                points.reverse();
+            }
+            let corners: Vec<Vector4> = points.iter().map(|p| [p[0], p[1], 0.0, 1.0]).collect();
+            let triangles = triangulate(&corners);
+            assert_eq!(triangles.len(), 4);
+
+            //every triangle winds like the face and together they add up to
+            //its area
+            let winding = if reversed {-1.0} else {1.0};
+            let area: f64 = triangles.iter()
+                .map(|[a, b, c]| signed_area(points[*a], points[*b], points[*c]) * winding)
+                .inspect(|area| assert!(*area > 0.0))
+                .sum();
+            assert!((area - 3.0).abs() < 1e-9);
+
+            //and none of them overlap or reach outside: points off the
+            //edges are covered once inside the L and never outside it
+            for i in 0..8 {
+                for j in 0..8 {
+                    let p = [i as f64 * 0.25 + 0.1, j as f64 * 0.25 + 0.17];
+                    let covered = triangles.iter().filter(|[a, b, c]| {
+                        let (a, b, c) = (points[*a], points[*b], points[*c]);
+                        if reversed {inside_triangle(p, a, c, b)} else {inside_triangle(p, a, b, c)}
+                    }).count();
+                    let in_l = p[0] < 1.0 || p[1] < 1.0;
+                    assert_eq!(covered, if in_l {1} else {0}, "{:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn triangulated_corners_keep_their_uv_and_normal() {
+        //vt and vn i are only used by v i, and tell which one it is
+        let mut obj = String::new();
+        for (i, p) in L_SHAPE.iter().enumerate() {
+            obj += &format!("v {} {} 0\nvt {} {}\nvn {} 0 1\n", p[0], p[1], p[0], p[1], i);
+        }
+        obj += "f 1/1/1 2/2/2 3/3/3 4/4/4 5/5/5 6/6/6\n";
+        let model = load_obj_file(&obj).unwrap();
+        assert_eq!(model.triangles.len(), 4);
+        for t in &model.triangles {
+            for corner in 0..3 {
+                let v = t.vertex[corner];
+                assert_eq!(t.uv_coord[corner], Some(v));
+                assert_eq!(t.normal[corner], Some(v));
+                let uv = model.corner_uv(t, corner).unwrap();
+                assert_eq!([uv[0], uv[1]], [model.vertices[v][0], model.vertices[v][1]]);
+            }
+        }
+    }
+
+    #[test]
+    fn corners_without_vt_or_vn_have_none() {
+        let model = load_obj_file("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5 0.5\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\nf 1 2 3\n").unwrap();
+        assert_eq!(model.triangles[0].normal, [Some(0); 3]);
+        assert_eq!(model.triangles[0].uv_coord, [Some(0); 3]);
+        assert_eq!(model.triangles[1].normal, [None; 3]);
+        assert_eq!(model.triangles[1].uv_coord, [None; 3]);
+        assert_eq!(model.corner_normal(&model.triangles[1], 0), None);
+        assert_eq!(model.corner_uv(&model.triangles[1], 0), None);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = load_obj_file("# a comment\nv 0 0 0\n\nv 1 x 0\n").unwrap_err();
+        assert_eq!(error.line, 4);
+        assert_eq!(error.kind, ObjErrorKind::BadNumber {keyword: "v".to_string(), value: "x".to_string()});
+
+        let error = load_obj_file("v 0 0 0\nv 1 0 0\nf 1 2 3\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.kind, ObjErrorKind::BadIndex {keyword: "v", index: "3".to_string()});
+
+        let error = load_obj_file("v 0 0\n").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(matches!(error.kind, ObjErrorKind::MissingValues {..}));
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let obj = "v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 1\nf -3/-2 -2/-1 -1/-1\n";
+        let model = load_obj_file(obj).unwrap();
+        assert_eq!(model.triangles[0].vertex, [1, 2, 3]);
+        assert_eq!(model.triangles[0].uv_coord, [Some(0), Some(1), Some(1)]);
+
+        //relative to what's defined so far, not to the whole file
+        let error = load_obj_file("v 0 0 0\nf -1 -2 -3\nv 1 0 0\nv 0 1 0\n").unwrap_err();
+        assert_eq!(error.kind, ObjErrorKind::BadIndex {keyword: "v", index: "-2".to_string()});
+    }
+
+    #[test]
+    fn reads_normals_without_uvs() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nvn 0 0 -1\nf 1//2 2//1 3//2\n";
+        let model = load_obj_file(obj).unwrap();
+        assert_eq!(model.triangles[0].normal, [Some(1), Some(0), Some(1)]);
+        assert_eq!(model.triangles[0].uv_coord, [None; 3]);
+        assert_eq!(model.corner_normal(&model.triangles[0], 0), Some([0.0, 0.0, -1.0]));
     }
-    
 }
@@ -0,0 +1,251 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::{self, File};
+use std::path::Path;
+
+use super::colors::{Color, from_u8_rgba, from_rgba_u8};
+use super::bitmaps::{Bitmap};
+use super::image_error::{ImageError, ImageResult};
+
+//reads the whitespace separated header tokens, skipping # comments
+struct Tokens<'data> {
+    data: &'data [u8],
+    pos: usize
+}
+
+impl<'data> Tokens<'data> {
+    fn next(&mut self) -> ImageResult<&'data str> {
+        loop {
+            match self.data.get(self.pos) {
+                Some(b'#') => {
+                    while !matches!(self.data.get(self.pos), Some(b'\n') | Some(b'\r') | None) {
+                        self.pos += 1;
+                    }
+                },
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return Err(ImageError::Truncated)
+            }
+        }
+        let start = self.pos;
+        while matches!(self.data.get(self.pos), Some(c) if !c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.data[start..self.pos])
+            .map_err(|_| ImageError::Corrupt("header is not ascii".to_string()))
+    }
+
+    fn number(&mut self) -> ImageResult<usize> {
+        let token = self.next()?;
+        token.parse().map_err(|_| ImageError::Corrupt(format!("expected a number, found {:?}", token)))
+    }
+
+    //the raster of the binary formats starts after exactly one whitespace
+    //character following the header
+    fn raster(&self) -> &'data [u8] {
+        &self.data[(self.pos + 1).min(self.data.len())..]
+    }
+}
+
+//turns `depth` samples (gray, gray+alpha, rgb or rgba) into a color
+fn to_color(samples: &[u32], maxval: u32) -> Color {
+    let s = |i: usize| (samples[i] * 255 / maxval) as u8;
+    match samples.len() {
+        1 => from_u8_rgba(s(0), s(0), s(0), 255),
+        2 => from_u8_rgba(s(0), s(0), s(0), s(1)),
+        3 => from_u8_rgba(s(0), s(1), s(2), 255),
+        _ => from_u8_rgba(s(0), s(1), s(2), s(3))
+    }
+}
+
+pub fn load_bitmap_from_netpbm<P: AsRef<Path>>(filename: P) -> ImageResult<Bitmap> {
+    decode_netpbm(&fs::read(filename)?)
+}
+
+//P1-P6 (plain and raw pbm, pgm and ppm) and P7 (pam)
+pub fn decode_netpbm(file: &[u8]) -> ImageResult<Bitmap> {
+    if file.len() < 2 || file[0] != b'P' || !(b'1'..=b'7').contains(&file[1]) {
+        return Err(ImageError::Corrupt("missing netpbm signature".to_string()));
+    }
+    let kind = file[1] - b'0';
+    let mut tokens = Tokens {data: file, pos: 2};
+
+    let (width, height, depth, maxval) = if kind == 7 {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        loop {
+            match tokens.next()? {
+                "WIDTH" => width = Some(tokens.number()?),
+                "HEIGHT" => height = Some(tokens.number()?),
+                "DEPTH" => depth = Some(tokens.number()?),
+                "MAXVAL" => maxval = Some(tokens.number()?),
+                //the depth already says everything we need
+                "TUPLTYPE" => {
+                    tokens.next()?;
+                },
+                "ENDHDR" => break,
+                other => return Err(ImageError::Corrupt(format!("unknown pam header field {}", other)))
+            }
+        }
+        let missing = || ImageError::Corrupt("incomplete pam header".to_string());
+        (width.ok_or_else(missing)?, height.ok_or_else(missing)?,
+         depth.ok_or_else(missing)?, maxval.ok_or_else(missing)?)
+    } else {
+        let width = tokens.number()?;
+        let height = tokens.number()?;
+        let maxval = if kind == 1 || kind == 4 {1} else {tokens.number()?};
+        let depth = if kind == 3 || kind == 6 {3} else {1};
+        (width, height, depth, maxval)
+    };
+
+    if !(1..=4).contains(&depth) {
+        return Err(ImageError::UnsupportedType(depth as u32));
+    }
+    if maxval == 0 || maxval > 65535 {
+        return Err(ImageError::UnsupportedBitDepth(maxval as u32));
+    }
+    let maxval = maxval as u32;
+
+    //every pixel takes up at least a bit of what's left of the file, so a
+    //header claiming more than that is lying and mustn't be allocated for
+    let remaining = file.len().saturating_sub(tokens.pos);
+    let pixels = width.checked_mul(height)
+        .filter(|p| *p <= remaining.saturating_mul(8))
+        .ok_or_else(|| ImageError::Corrupt(format!("{}x{} pixels don't fit in the file", width, height)))?;
+
+    let mut data = vec![0u32; pixels];
+    let mut samples = vec![0u32; depth];
+    match kind {
+        //pbm: 1 is black
+        1 => {
+            for item in &mut data {
+                let token = tokens.next()?;
+                //plain pbm doesn't need whitespace between the digits
+                tokens.pos -= token.len() - 1;
+                let v = if token.as_bytes()[0] == b'1' {0} else {255};
+                *item = from_u8_rgba(v, v, v, 255);
+            }
+        },
+        4 => {
+            let raster = tokens.raster();
+            let stride = width.div_ceil(8);
+            if raster.len() < stride * height {
+                return Err(ImageError::Truncated);
+            }
+            for (y, line) in data.chunks_mut(width.max(1)).enumerate() {
+                for (x, item) in line.iter_mut().enumerate() {
+                    let bit = (raster[y * stride + x / 8] >> (7 - x % 8)) & 1;
+                    let v = if bit == 1 {0} else {255};
+                    *item = from_u8_rgba(v, v, v, 255);
+                }
+            }
+        },
+        2 | 3 => {
+            for item in &mut data {
+                for sample in samples.iter_mut() {
+                    *sample = (tokens.number()? as u32).min(maxval);
+                }
+                *item = to_color(&samples, maxval);
+            }
+        },
+        _ => {
+            let raster = tokens.raster();
+            let bytes = if maxval > 255 {2} else {1};
+            if raster.len() < pixels.saturating_mul(depth * bytes) {
+                return Err(ImageError::Truncated);
+            }
+            for (item, pixel) in data.iter_mut().zip(raster.chunks(depth * bytes)) {
+                for (i, sample) in samples.iter_mut().enumerate() {
+                    *sample = if bytes == 2 {
+                        (pixel[2 * i] as u32) << 8 | pixel[2 * i + 1] as u32
+                    } else {
+                        pixel[i] as u32
+                    }.min(maxval);
+                }
+                *item = to_color(&samples, maxval);
+            }
+        }
+    }
+
+    Ok(Bitmap {width, height, data})
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetpbmFormat {
+    //P5, luminance only
+    Pgm,
+    //P6
+    Ppm,
+    //P7 with an RGB_ALPHA tuple type
+    Pam
+}
+
+pub fn save_bitmap_as_netpbm<P: AsRef<Path>>(bitmap: &Bitmap, filename: P, format: NetpbmFormat) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(filename)?);
+    write_netpbm(bitmap, &mut f, format)?;
+    f.flush()
+}
+
+//writes the binary variant of the format; frames can be written one after
+//another to the same stream, which is what e.g. `ffmpeg -f image2pipe`
+//expects
+pub fn write_netpbm<W: Write>(bitmap: &Bitmap, f: &mut W, format: NetpbmFormat) -> io::Result<()> {
+    let header = match format {
+        NetpbmFormat::Pgm => format!("P5\n{} {}\n255\n", bitmap.width, bitmap.height),
+        NetpbmFormat::Ppm => format!("P6\n{} {}\n255\n", bitmap.width, bitmap.height),
+        NetpbmFormat::Pam => format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            bitmap.width, bitmap.height
+        )
+    };
+    f.write_all(header.as_bytes())?;
+
+    let mut line = Vec::with_capacity(bitmap.width * 4);
+    for row in bitmap.data.chunks(bitmap.width.max(1)).take(bitmap.height) {
+        line.clear();
+        for color in row {
+            let (r, g, b, a) = from_rgba_u8(*color);
+            match format {
+                NetpbmFormat::Pgm => line.push(
+                    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114 + 500) / 1000) as u8
+                ),
+                NetpbmFormat::Ppm => line.extend_from_slice(&[r, g, b]),
+                NetpbmFormat::Pam => line.extend_from_slice(&[r, g, b, a])
+            }
+        }
+        f.write_all(&line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(bitmap: &Bitmap, format: NetpbmFormat) -> Vec<u8> {
+        let mut file = vec![];
+        write_netpbm(bitmap, &mut file, format).unwrap();
+        file
+    }
+
+    #[test]
+    fn round_trips() {
+        let colors = vec![
+            from_u8_rgba(255, 0, 0, 255), from_u8_rgba(0, 255, 0, 128), from_u8_rgba(0, 0, 255, 0),
+            from_u8_rgba(10, 20, 30, 255), from_u8_rgba(40, 50, 60, 1), from_u8_rgba(200, 100, 50, 255)
+        ];
+        let bitmap = Bitmap {width: 3, height: 2, data: colors.clone()};
+        assert_eq!(decode_netpbm(&encode(&bitmap, NetpbmFormat::Pam)).unwrap().data, colors);
+
+        let opaque: Vec<Color> = colors.iter().map(|c| {
+            let (r, g, b, _) = from_rgba_u8(*c);
+            from_u8_rgba(r, g, b, 255)
+        }).collect();
+        assert_eq!(decode_netpbm(&encode(&bitmap, NetpbmFormat::Ppm)).unwrap().data, opaque);
+
+        //gray pixels stay the same through pgm
+        let gray: Vec<Color> = [0, 17, 128, 200, 254, 255].iter().map(|v| from_u8_rgba(*v, *v, *v, 255)).collect();
+        let bitmap = Bitmap {width: 3, height: 2, data: gray.clone()};
+        assert_eq!(decode_netpbm(&encode(&bitmap, NetpbmFormat::Pgm)).unwrap().data, gray);
+    }
+}
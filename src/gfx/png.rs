@@ -0,0 +1,462 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::{self, File};
+use std::path::Path;
+
+use super::colors::{Color, from_u8_rgba, from_rgba_u8};
+use super::bitmaps::{Bitmap};
+use super::image_error::{ImageError, ImageResult};
+use super::zlib::{zlib_compress, zlib_decompress};
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {0xedb88320 ^ (c >> 1)} else {c >> 1};
+        }
+        *entry = c;
+    }
+    table
+}
+
+//crc of the chunk type followed by the chunk data
+fn crc32(table: &[u32; 256], parts: &[&[u8]]) -> u32 {
+    let mut c = 0xffffffffu32;
+    for part in parts {
+        for byte in *part {
+            c = table[((c ^ *byte as u32) & 255) as usize] ^ (c >> 8);
+        }
+    }
+    c ^ 0xffffffff
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+//reverses the per-scanline filters, `bpp` being the distance in
+//bytes to the corresponding byte of the pixel on the left
+fn unfilter(data: &[u8], rows: usize, stride: usize, bpp: usize) -> ImageResult<Vec<u8>> {
+    let mut out = vec![0u8; rows * stride];
+    for y in 0..rows {
+        let filter = data[y * (stride + 1)];
+        let line = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prior = if y == 0 {None} else {Some(&done[(y - 1) * stride..])};
+        let current = &mut rest[..stride];
+
+        for x in 0..stride {
+            let a = if x >= bpp {current[x - bpp]} else {0};
+            let b = prior.map_or(0, |p| p[x]);
+            let c = if x >= bpp {prior.map_or(0, |p| p[x - bpp])} else {0};
+            current[x] = match filter {
+                0 => line[x],
+                1 => line[x].wrapping_add(a),
+                2 => line[x].wrapping_add(b),
+                3 => line[x].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => line[x].wrapping_add(paeth(a, b, c)),
+                _ => return Err(ImageError::Corrupt(format!("unknown filter type {}", filter)))
+            };
+        }
+    }
+    Ok(out)
+}
+
+struct Format {
+    color_type: u8,
+    depth: usize,
+    channels: usize,
+    palette: Vec<Color>,
+    //tRNS for grayscale and truecolor images: the one sample value that is
+    //fully transparent, at the image's own bit depth
+    transparent: Option<[u16; 3]>
+}
+
+impl Format {
+    fn bits_per_pixel(&self) -> usize {
+        self.channels * self.depth
+    }
+
+    //reads the `i`th sample of a scanline at the image's bit depth
+    fn sample(&self, line: &[u8], i: usize) -> u16 {
+        match self.depth {
+            16 => (line[2 * i] as u16) << 8 | line[2 * i + 1] as u16,
+            8 => line[i] as u16,
+            _ => {
+                let bit = i * self.depth;
+                let shift = 8 - self.depth - bit % 8;
+                ((line[bit / 8] >> shift) & ((1 << self.depth) - 1) as u8) as u16
+            }
+        }
+    }
+
+    //scales a sample to 0-255
+    fn to_u8(&self, value: u16) -> u8 {
+        match self.depth {
+            16 => (value >> 8) as u8,
+            8 => value as u8,
+            _ => (value as u32 * 255 / ((1 << self.depth) - 1)) as u8
+        }
+    }
+
+    fn pixel(&self, line: &[u8], x: usize) -> ImageResult<Color> {
+        let s = |c: usize| self.sample(line, x * self.channels + c);
+        let opaque = |key: [u16; 3]| if self.transparent == Some(key) {0} else {255};
+        Ok(match self.color_type {
+            0 => {
+                let g = s(0);
+                let v = self.to_u8(g);
+                from_u8_rgba(v, v, v, opaque([g, 0, 0]))
+            },
+            2 => {
+                let (r, g, b) = (s(0), s(1), s(2));
+                from_u8_rgba(self.to_u8(r), self.to_u8(g), self.to_u8(b), opaque([r, g, b]))
+            },
+            3 => {
+                let index = s(0) as usize;
+                *self.palette.get(index).ok_or_else(|| ImageError::Corrupt(
+                    format!("palette index {} out of range", index)
+                ))?
+            },
+            4 => {
+                let v = self.to_u8(s(0));
+                from_u8_rgba(v, v, v, self.to_u8(s(1)))
+            },
+            _ => from_u8_rgba(self.to_u8(s(0)), self.to_u8(s(1)), self.to_u8(s(2)), self.to_u8(s(3)))
+        })
+    }
+}
+
+//Adam7 passes: (x start, y start, x step, y step)
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2)
+];
+
+pub fn load_bitmap_from_png<P: AsRef<Path>>(filename: P) -> ImageResult<Bitmap> {
+    decode_png(&fs::read(filename)?)
+}
+
+pub fn decode_png(file: &[u8]) -> ImageResult<Bitmap> {
+    if file.len() < 8 || file[..8] != SIGNATURE {
+        return Err(ImageError::Corrupt("missing png signature".to_string()));
+    }
+
+    let table = crc32_table();
+    let mut pos = 8;
+    let mut header: Option<&[u8]> = None;
+    let mut palette = Vec::new();
+    let mut transparency: Option<&[u8]> = None;
+    let mut compressed = Vec::new();
+
+    loop {
+        let chunk_header = file.get(pos..pos+8).ok_or(ImageError::Truncated)?;
+        let length = be_u32(chunk_header) as usize;
+        let kind = &chunk_header[4..8];
+        let body = file.get(pos+8..pos+8+length).ok_or(ImageError::Truncated)?;
+        let crc = file.get(pos+8+length..pos+12+length).ok_or(ImageError::Truncated)?;
+        if be_u32(crc) != crc32(&table, &[kind, body]) {
+            return Err(ImageError::Corrupt(format!("bad crc in {} chunk", String::from_utf8_lossy(kind))));
+        }
+        pos += 12 + length;
+
+        match kind {
+            b"IHDR" => {
+                if length != 13 {
+                    return Err(ImageError::Corrupt("IHDR has the wrong length".to_string()));
+                }
+                header = Some(body);
+            },
+            b"PLTE" => {
+                palette = body.chunks_exact(3)
+                    .map(|rgb| from_u8_rgba(rgb[0], rgb[1], rgb[2], 255))
+                    .collect();
+            },
+            b"tRNS" => transparency = Some(body),
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {
+                //ancillary chunks have a lowercase first letter and can be
+                //skipped, critical ones can't
+                if kind[0] & 32 == 0 {
+                    return Err(ImageError::Corrupt(
+                        format!("unknown critical chunk {}", String::from_utf8_lossy(kind))
+                    ));
+                }
+            }
+        }
+    }
+
+    let header = header.ok_or_else(|| ImageError::Corrupt("missing IHDR chunk".to_string()))?;
+    let width = be_u32(&header[0..4]) as usize;
+    let height = be_u32(&header[4..8]) as usize;
+    let depth = header[8] as usize;
+    let color_type = header[9];
+    let interlace = header[12];
+
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(ImageError::UnsupportedType(color_type as u32))
+    };
+    let depth_ok = match color_type {
+        0 => matches!(depth, 1 | 2 | 4 | 8 | 16),
+        3 => matches!(depth, 1 | 2 | 4 | 8),
+        _ => matches!(depth, 8 | 16)
+    };
+    if !depth_ok {
+        return Err(ImageError::UnsupportedBitDepth(depth as u32));
+    }
+    if header[10] != 0 || header[11] != 0 || interlace > 1 {
+        return Err(ImageError::UnsupportedType(color_type as u32));
+    }
+
+    let mut transparent = None;
+    if let Some(trns) = transparency {
+        match color_type {
+            3 => {
+                for (color, alpha) in palette.iter_mut().zip(trns) {
+                    let (r, g, b, _) = from_rgba_u8(*color);
+                    *color = from_u8_rgba(r, g, b, *alpha);
+                }
+            },
+            0 if trns.len() >= 2 => {
+                transparent = Some([(trns[0] as u16) << 8 | trns[1] as u16, 0, 0]);
+            },
+            2 if trns.len() >= 6 => {
+                let s = |i: usize| (trns[i] as u16) << 8 | trns[i + 1] as u16;
+                transparent = Some([s(0), s(2), s(4)]);
+            },
+            _ => ()
+        }
+    }
+    if color_type == 3 && palette.is_empty() {
+        return Err(ImageError::Corrupt("palette image without a PLTE chunk".to_string()));
+    }
+
+    let format = Format {color_type, depth, channels, palette, transparent};
+    let bpp = format.bits_per_pixel().div_ceil(8);
+
+    let passes: Vec<(usize, usize, usize, usize)> = if interlace == 1 {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+
+    //every filtered line is a filter byte and the packed pixels, so the
+    //header says exactly how much inflated data there should be; more than
+    //that is rejected while inflating rather than after
+    let expected = passes.iter().try_fold(0usize, |total, (x0, y0, dx, dy)| {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            return Some(total);
+        }
+        let stride = pass_width.checked_mul(format.bits_per_pixel())?.div_ceil(8);
+        total.checked_add(pass_height.checked_mul(stride + 1)?)
+    }).ok_or_else(|| ImageError::Corrupt(format!("{}x{} pixels are too many", width, height)))?;
+    let raw = zlib_decompress(&compressed, expected)?;
+
+    //the header can claim any size, but every pixel takes up at least
+    //bits_per_pixel bits of the inflated data, so check that before
+    //allocating anything
+    let pixels = width.checked_mul(height)
+        .filter(|p| p.checked_mul(format.bits_per_pixel()).is_some_and(|bits| bits <= raw.len() * 8))
+        .ok_or_else(|| ImageError::Corrupt(format!("{}x{} pixels don't fit in the image data", width, height)))?;
+
+    let mut data = vec![0u32; pixels];
+    let mut offset = 0;
+    for (x0, y0, dx, dy) in passes {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let stride = (pass_width * format.bits_per_pixel()).div_ceil(8);
+        let size = pass_height * (stride + 1);
+        let filtered = raw.get(offset..offset + size).ok_or(ImageError::Truncated)?;
+        offset += size;
+
+        let lines = unfilter(filtered, pass_height, stride, bpp)?;
+        for (py, line) in lines.chunks(stride).enumerate() {
+            for px in 0..pass_width {
+                data[(y0 + py * dy) * width + x0 + px * dx] = format.pixel(line, px)?;
+            }
+        }
+    }
+
+    Ok(Bitmap {width, height, data})
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PngOptions {
+    //rgba (color type 6) if true, rgb (color type 2) otherwise
+    pub alpha: bool
+}
+
+impl Default for PngOptions {
+    fn default() -> PngOptions {
+        PngOptions {
+            alpha: true
+        }
+    }
+}
+
+fn write_chunk<W: Write>(f: &mut W, table: &[u32; 256], kind: &[u8], body: &[u8]) -> io::Result<()> {
+    f.write_all(&(body.len() as u32).to_be_bytes())?;
+    f.write_all(kind)?;
+    f.write_all(body)?;
+    f.write_all(&crc32(table, &[kind, body]).to_be_bytes())
+}
+
+pub fn save_bitmap_as_png<P: AsRef<Path>>(bitmap: &Bitmap, filename: P, options: PngOptions) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(filename)?);
+    write_png(bitmap, &mut f, options)?;
+    f.flush()
+}
+
+pub fn write_png<W: Write>(bitmap: &Bitmap, f: &mut W, options: PngOptions) -> io::Result<()> {
+    let table = crc32_table();
+    let bpp = if options.alpha {4} else {3};
+    let stride = bitmap.width * bpp;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(bitmap.width as u32).to_be_bytes());
+    header.extend_from_slice(&(bitmap.height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, if options.alpha {6} else {2}, 0, 0, 0]);
+
+    let mut raw = Vec::with_capacity(bitmap.height * (stride + 1));
+    let mut prior = vec![0u8; stride];
+    let mut line = Vec::with_capacity(stride);
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+    for row in bitmap.data.chunks(bitmap.width.max(1)).take(bitmap.height) {
+        line.clear();
+        for color in row {
+            let (r, g, b, a) = from_rgba_u8(*color);
+            line.extend_from_slice(&[r, g, b, a][..bpp]);
+        }
+
+        //pick the filter with the smallest sum of absolute differences, the
+        //usual heuristic for telling which one will compress best
+        let mut best_filter = 0;
+        let mut best_score = u64::MAX;
+        for filter in 0..5u8 {
+            for x in 0..stride {
+                let a = if x >= bpp {line[x - bpp]} else {0};
+                let b = prior[x];
+                let c = if x >= bpp {prior[x - bpp]} else {0};
+                candidate[x] = line[x].wrapping_sub(match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c)
+                });
+            }
+            let score = candidate.iter().map(|v| (*v as i8).unsigned_abs() as u64).sum();
+            if score < best_score {
+                best_score = score;
+                best_filter = filter;
+                best.copy_from_slice(&candidate);
+            }
+        }
+        raw.push(best_filter);
+        raw.extend_from_slice(&best);
+        prior.copy_from_slice(&line);
+    }
+
+    f.write_all(&SIGNATURE)?;
+    write_chunk(f, &table, b"IHDR", &header)?;
+    write_chunk(f, &table, b"IDAT", &zlib_compress(&raw))?;
+    write_chunk(f, &table, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(bitmap: &Bitmap, alpha: bool) -> Vec<u8> {
+        let mut file = Vec::new();
+        write_png(bitmap, &mut file, PngOptions {alpha}).unwrap();
+        file
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = (0..35u32).map(|i| {
+            let i = i as u8;
+            from_u8_rgba(i.wrapping_mul(7), i.wrapping_mul(13), 255 - i, i.wrapping_mul(37))
+        }).collect();
+        let bitmap = Bitmap {width: 7, height: 5, data};
+        assert_eq!(decode_png(&encode(&bitmap, true)).unwrap().data, bitmap.data);
+
+        //without alpha everything comes back opaque
+        let decoded = decode_png(&encode(&bitmap, false)).unwrap();
+        let opaque: Vec<Color> = bitmap.data.iter().map(|c| c | 0xff000000).collect();
+        assert_eq!((decoded.width, decoded.height), (7, 5));
+        assert_eq!(decoded.data, opaque);
+    }
+
+    //a 3x2 rgba image written with python's zlib, its rows using the sub
+    //and paeth filters
+    #[test]
+    fn decodes_foreign_png() {
+        let file = [
+            137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 3, 0, 0, 0, 2,
+            8, 6, 0, 0, 0, 157, 116, 102, 26, 0, 0, 0, 34, 73, 68, 65, 84, 120, 218, 99, 252,
+            207, 192, 240, 159, 241, 63, 67, 35, 3, 227, 255, 6, 22, 110, 17, 57, 6, 13, 99, 185,
+            250, 5, 41, 198, 245, 0, 111, 62, 7, 240, 72, 169, 215, 247, 0, 0, 0, 0, 73, 69, 78,
+            68, 174, 66, 96, 130
+        ];
+        let bitmap = decode_png(&file).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (3, 2));
+        assert_eq!(bitmap.data, vec![
+            from_u8_rgba(255, 0, 0, 255), from_u8_rgba(0, 255, 0, 128), from_u8_rgba(0, 0, 255, 0),
+            from_u8_rgba(10, 20, 30, 255), from_u8_rgba(40, 50, 60, 255), from_u8_rgba(200, 100, 50, 255)
+        ]);
+    }
+
+    #[test]
+    fn rejects_size_larger_than_data() {
+        let mut file = encode(&Bitmap {width: 2, height: 2, data: vec![0; 4]}, true);
+        //claim 65535x65535 and fix up the IHDR crc
+        file[16..24].copy_from_slice(&[0, 0, 255, 255, 0, 0, 255, 255]);
+        let crc = crc32(&crc32_table(), &[&file[12..16], &file[16..29]]);
+        file[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(decode_png(&file), Err(ImageError::Corrupt(_))));
+    }
+
+    #[test]
+    fn rejects_more_data_than_header_needs() {
+        let mut file = encode(&Bitmap {width: 2, height: 2, data: vec![0; 4]}, true);
+        //claim 1x1, the 2x2 pixels then inflate past what that needs
+        file[16..24].copy_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+        let crc = crc32(&crc32_table(), &[&file[12..16], &file[16..29]]);
+        file[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(decode_png(&file), Err(ImageError::Corrupt(_))));
+    }
+}
@@ -1,11 +1,12 @@
 use std::mem;
 use super::bitmaps::{Bitmap};
 use super::colors::{Color};
+use super::sampler::Sampler;
 
 
 
 pub fn putpixel(buffer: &mut Bitmap, x: isize, y:isize, color: Color){
-    if x < (buffer.width/2) as isize && x > (buffer.width/2) as isize*-1 {
+    if x < (buffer.width/2) as isize && x > -((buffer.width/2) as isize) {
         let x = (buffer.width/2) as isize + x;
         let y = (buffer.height/2) as isize - y;
         if let Some(pixel) = buffer.data.get_mut((y*buffer.width as isize + x) as usize) {
@@ -14,6 +15,16 @@ pub fn putpixel(buffer: &mut Bitmap, x: isize, y:isize, color: Color){
     }
 }
 
+//the color putpixel would overwrite, if the point is on the bitmap
+pub fn getpixel(buffer: &Bitmap, x: isize, y: isize) -> Option<Color> {
+    if x < (buffer.width/2) as isize && x > -((buffer.width/2) as isize) {
+        let x = (buffer.width/2) as isize + x;
+        let y = (buffer.height/2) as isize - y;
+        return buffer.data.get((y*buffer.width as isize + x) as usize).copied();
+    }
+    None
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Interpolate {
     pub a: f32,
@@ -28,6 +39,10 @@ impl Interpolate {
         (self.end - self.current).abs()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.current == self.end
+    }
+
     pub fn empty() -> Interpolate {
         Interpolate{
             a: 0.0,
@@ -47,8 +62,8 @@ impl Iterator for Interpolate {
             None
         } else {
             let d = self.d;
-            self.d = self.d + self.a;
-            self.current = self.current + 1;
+            self.d += self.a;
+            self.current += 1;
             Some(d)
         }
     }
@@ -78,10 +93,10 @@ pub fn interpolate_old(i0:isize, d0:isize, i1:isize, d1:isize) -> Vec<f64> {
     let mut d = d0 as f64;
     for _i in i0..(i1+1) {
         values.push(d);
-        d = d + a;
+        d += a;
     }
     
-    return values;
+    values
 }
 
 
@@ -120,63 +135,81 @@ pub fn draw_wireframe_triangle(buffer: &mut Bitmap, p0: (isize, isize), p1: (isi
     draw_line(buffer, p2, p0, color);
 }
 
-pub fn draw_filled_triangle(buffer: &mut Bitmap, p0: (isize, isize), p1: (isize, isize), p2: (isize, isize), color: Color){
-
-    let c : [(isize, isize); 3] = [p0, p1, p2];
-    let mut lowest_point = 0;
-    let mut medium_point = 1;
-    let mut highest_point = 2;
-
-    //ensures the ordering
-    if c[medium_point].1 < c[lowest_point].1 {
-        mem::swap(&mut medium_point, &mut lowest_point);
+//canvas positions get snapped to 1/SUBPIXEL_STEPS of a pixel, which keeps
+//the edge tests in rasterize_triangle exact integer math
+const SUBPIXEL_STEPS: f64 = 256.0;
+
+//calls `pixel(x, y, weights)` for every pixel of a triangle, taking the
+//corners in the same centered, y up canvas coordinates as putpixel (but
+//with sub-pixel precision) and skipping pixels outside a width by height
+//canvas. A pixel belongs to the triangle when the point (x, y) lies inside
+//it; `weights` are the barycentric coordinates of that point, linear on
+//screen. Points exactly on an edge only count for top and left edges, so
+//triangles sharing an edge never both draw a pixel along it, nor both
+//leave it out. Either winding works.
+pub fn rasterize_triangle(width: usize, height: usize, corners: [(f64, f64); 3],
+                          mut pixel: impl FnMut(isize, isize, [f64; 3])) {
+    let step = SUBPIXEL_STEPS as i64;
+    let mut p = corners.map(|c| ((c.0 * SUBPIXEL_STEPS).round() as i64, (c.1 * SUBPIXEL_STEPS).round() as i64));
+    //positive when c is to the left of a->b
+    let edge = |a: (i64, i64), b: (i64, i64), c: (i64, i64)| (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+
+    //turn the triangle counterclockwise, remembering where the corners went
+    let mut order = [0, 1, 2];
+    let mut area = edge(p[0], p[1], p[2]);
+    if area == 0 {
+        return;
     }
-    if c[highest_point].1 < c[lowest_point].1 {
-        mem::swap(&mut highest_point, &mut lowest_point);
+    if area < 0 {
+        p.swap(1, 2);
+        order.swap(1, 2);
+        area = -area;
     }
-    if c[highest_point].1 < c[medium_point].1 {
-        mem::swap(&mut highest_point, &mut medium_point);
-    }
-
-    let (x0, y0) = c[lowest_point];
-    let (x1, y1) = c[medium_point];
-    let (x2, y2) = c[highest_point];
-
-    //we want exactly one value of x for a value of y
-    let mut x0_to_x1 = interpolate(y0, x0 as f32, y1, x1 as f32);
-    let mut x1_to_x2 = interpolate(y1, x1 as f32, y2, x2 as f32);
-    let mut x0_to_x2 = interpolate(y0, x0 as f32, y2, x2 as f32);
 
-    //Concatenate the (vertically) short sides
-    x0_to_x1.end -= 1; //remove the last element to avoid repetition
-    let m = (x0_to_x1.len() + x1_to_x2.len() / 2) as usize; 
-    
-    let x0_to_x1_to_x2 = x0_to_x1.chain(x1_to_x2);
-    
-    let (x_left, x_right);
-    
-    //we check the middle horizontal lines to see whether the vertically
-    //longest line is to the left or to the right of the other two
-    //@TODO: there should be a faster way to obtain these numbers
-    let (m_x0_x2, m_x0_x1_x2) = x0_to_x2.clone().zip(x0_to_x1_to_x2.clone()).nth(m).unwrap();
-
-    //@TODO: do I really need to do this weird stuff with chaining?
-    if x0_to_x2.len() > 1 && m_x0_x2 < m_x0_x1_x2 {
-        x_left = x0_to_x2.chain(Interpolate::empty());
-        x_right = x0_to_x1_to_x2;
-    } else {
-        x_left = x0_to_x1_to_x2;
-        x_right = x0_to_x2.chain(Interpolate::empty());
-    }
-    
-    for (y, (xl, xr)) in (y0..y2).zip(x_left.zip(x_right)) {
-        let (xl, xr) = (xl as isize, xr as isize);
-        for x in xl..xr {
-            putpixel(buffer, x, y, color);
+    //the pixels around the triangle that are on the canvas
+    let floor = |v: i64| v.div_euclid(step) as isize;
+    let ceil = |v: i64| -(-v).div_euclid(step) as isize;
+    let (half_width, half_height) = ((width / 2) as isize, (height / 2) as isize);
+    let x_min = ceil(p.iter().map(|c| c.0).min().unwrap()).max(1 - half_width);
+    let x_max = floor(p.iter().map(|c| c.0).max().unwrap()).min(half_width - 1);
+    let y_min = ceil(p.iter().map(|c| c.1).min().unwrap()).max(half_height - height as isize + 1);
+    let y_max = floor(p.iter().map(|c| c.1).max().unwrap()).min(half_height);
+
+    //edge k runs between the two corners other than k. With the triangle
+    //counterclockwise and y going up, top edges run towards -x and left
+    //edges towards -y; the others need to be strictly positive.
+    let edges = [0, 1, 2].map(|k| {
+        let (a, b) = (p[(k + 1) % 3], p[(k + 2) % 3]);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let bias = if dy < 0 || (dy == 0 && dx < 0) {0} else {-1};
+        (a, dx, dy, bias)
+    });
+
+    for y in y_min..=y_max {
+        let (px, py) = (x_min as i64 * step, y as i64 * step);
+        let mut e = edges.map(|(a, dx, dy, _)| dx * (py - a.1) - dy * (px - a.0));
+        for x in x_min..=x_max {
+            if (0..3).all(|k| e[k] + edges[k].3 >= 0) {
+                let mut weights = [0.0; 3];
+                for k in 0..3 {
+                    weights[order[k]] = e[k] as f64 / area as f64;
+                }
+                pixel(x, y, weights);
+            }
+            for k in 0..3 {
+                e[k] -= edges[k].2 * step;
+            }
         }
-    }  
+    }
+}
+
+pub fn draw_filled_triangle(buffer: &mut Bitmap, p0: (isize, isize), p1: (isize, isize), p2: (isize, isize), color: Color){
+    let corners = [p0, p1, p2].map(|p| (p.0 as f64, p.1 as f64));
+    let (width, height) = (buffer.width, buffer.height);
+    rasterize_triangle(width, height, corners, |x, y, _| putpixel(buffer, x, y, color));
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn draw_textured_triangle(buffer: &mut Bitmap,
                               p0: (isize, isize),
                               p1: (isize, isize),
@@ -185,127 +218,65 @@ pub fn draw_textured_triangle(buffer: &mut Bitmap,
                               uv1: (f64, f64),
                               uv2: (f64, f64),
                               texture: &Bitmap){
-    
-    let c : [(isize, isize); 3] = [p0, p1, p2];
-    let u : [(isize, isize); 3] =[
-        ((uv0.0 * texture.width as f64) as isize,
-         (uv0.1 * texture.height as f64) as isize),
-        ((uv1.0 * texture.width as f64) as isize,
-         (uv1.1 * texture.height as f64) as isize),
-        ((uv2.0 * texture.width as f64) as isize,
-         (uv2.1 * texture.height as f64) as isize),
-    ];
+    let corners = [p0, p1, p2].map(|p| (p.0 as f64, p.1 as f64));
+    let uv = [uv0, uv1, uv2];
+    let sampler = Sampler::default();
+    let (width, height) = (buffer.width, buffer.height);
+    rasterize_triangle(width, height, corners, |x, y, weights| {
+        //sample the uv coordinates from the texture
+        let u = (0..3).map(|i| uv[i].0 * weights[i]).sum();
+        let v = (0..3).map(|i| uv[i].1 * weights[i]).sum();
+        putpixel(buffer, x, y, sampler.sample(texture, (u, v)));
+    });
+}
 
-    
-    
-    let mut lowest_point = 0;
-    let mut medium_point = 1;
-    let mut highest_point = 2;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
 
-    //ensures the ordering
-    if c[medium_point].1 < c[lowest_point].1 {
-        mem::swap(&mut medium_point, &mut lowest_point);
-    }
-    if c[highest_point].1 < c[lowest_point].1 {
-        mem::swap(&mut highest_point, &mut lowest_point);
-    }
-    if c[highest_point].1 < c[medium_point].1 {
-        mem::swap(&mut highest_point, &mut medium_point);
+    //how many times each pixel gets drawn
+    fn coverage(triangles: &[[(f64, f64); 3]]) -> HashMap<(isize, isize), usize> {
+        let mut hits = HashMap::new();
+        for corners in triangles {
+            rasterize_triangle(40, 40, *corners, |x, y, _| *hits.entry((x, y)).or_insert(0) += 1);
+        }
+        hits
     }
 
-    let (x0, y0) = c[lowest_point];
-    let (x1, y1) = c[medium_point];
-    let (x2, y2) = c[highest_point];
-
-    //we want exactly one value of x for a value of y
-    let mut x0_to_x1 = interpolate(y0, x0 as f32, y1, x1 as f32);
-    let mut x1_to_x2 = interpolate(y1, x1 as f32, y2, x2 as f32);
-    let mut x0_to_x2 = interpolate(y0, x0 as f32, y2, x2 as f32);
-
-    //Concatenate the (vertically) short sides
-    x0_to_x1.end -= 1; //remove the last element to avoid repetition
-    let m = (x0_to_x1.len() + x1_to_x2.len() / 2) as usize;
-    let x0_to_x1_to_x2 = x0_to_x1.chain(x1_to_x2);
-    
-
-    //now we obtain the uv coordinates
-
-    let (u0, v0) = u[lowest_point];
-    let (u1, v1) = u[medium_point];
-    let (u2, v2) = u[highest_point];
-
-    
-    let mut u0_to_u1 = interpolate(y0, u0 as f32, y1, u1 as f32);
-    let mut u1_to_u2 = interpolate(y1, u1 as f32, y2, u2 as f32);
-    let mut u0_to_u2 = interpolate(y0, u0 as f32, y2, u2 as f32);    
-
-    //since we can't assume anything about the uv mapping, we need to
-    //calculate the v values as well (I think?)
-    let mut v0_to_v1 = interpolate(y0, v0 as f32, y1, v1 as f32);
-    let mut v1_to_v2 = interpolate(y1, v1 as f32, y2, v2 as f32);
-    let mut v0_to_v2 = interpolate(y0, v0 as f32, y2, v2 as f32);
-
-    //Concatenate the (vertically) short sides
-    u0_to_u1.end -= 1;
-    
-    let u0_to_u1_to_u2 = u0_to_u1.chain(u1_to_u2);
-    
-    v0_to_v1.end -= 1;
-    
-    let v0_to_v1_to_v2 = v0_to_v1.chain(v1_to_v2);
-
-    let (x_left, x_right);
-
-    let (u_left, u_right);
-    let (v_left, v_right);
-    
-    //we check the middle horizontal lines to see whether the vertically
-    //longest line is to the left or to the right of the other two
-    //@TODO: there should be a faster way to obtain these numbers
-    let (m_x0_x2, m_x0_x1_x2) = x0_to_x2.clone().zip(x0_to_x1_to_x2.clone()).nth(m).unwrap();
-
-    if x0_to_x2.len() > 1 &&  m_x0_x2 < m_x0_x1_x2 {
-        x_left = x0_to_x2.chain(Interpolate::empty());;
-        x_right = x0_to_x1_to_x2;
-
-        u_left = u0_to_u2.chain(Interpolate::empty());;
-        u_right = u0_to_u1_to_u2;
-        v_left = v0_to_v2.chain(Interpolate::empty());;
-        v_right = v0_to_v1_to_v2;
-        
-    } else {
-        x_left = x0_to_x1_to_x2;
-        x_right = x0_to_x2.chain(Interpolate::empty());
+    #[test]
+    fn shared_diagonal_draws_each_pixel_once() {
+        //corners on pixel centers, so whole rows and columns of centers lie
+        //on the edges and the diagonal
+        let (a, b, c, d) = ((-5.0, -5.0), (5.0, -5.0), (5.0, 5.0), (-5.0, 5.0));
+        let hits = coverage(&[[a, b, c], [a, c, d]]);
+        assert!(hits.values().all(|count| *count == 1));
+        assert_eq!(hits.len(), 100);
+    }
 
-        u_left = u0_to_u1_to_u2;
-        u_right = u0_to_u2.chain(Interpolate::empty());
-        v_left = v0_to_v1_to_v2;
-        v_right = v0_to_v2.chain(Interpolate::empty());
+    #[test]
+    fn square_covers_width_times_height() {
+        let (a, b, c, d) = ((-5.5, -3.5), (4.5, -3.5), (4.5, 2.5), (-5.5, 2.5));
+        let hits = coverage(&[[a, b, c], [a, c, d]]);
+        assert_eq!(hits.len(), 10 * 6);
+        assert!(hits.contains_key(&(-5, -3)) && hits.contains_key(&(4, 2)));
     }
-    
-    
 
-    for (y, (((xl, xr), (ul, ur)), (vl, vr))) in (y0..y2)
-        .zip(x_left.zip(x_right)
-        .zip(u_left.zip(u_right))
-        .zip(v_left.zip(v_right))) {
-            
-        //find the uv coordinates of each pixel in the y-line
-        let u_coords = interpolate(xl as isize, ul, xr as isize, ur);
-        
-        let v_coords = interpolate(xl as isize, vl, xr as isize, vr);
-        let (xl, xr) = (xl as isize, xr as isize);
-        for (x, (u, v)) in (xl..xr).zip(u_coords.zip(v_coords)) {
-            //sample the uv coordinates from the texture
-            let u = u as usize % texture.width;
-            let v = v as usize % texture.height;
-           
-            let color = texture.data[v*texture.width+u];
-            putpixel(buffer, x, y, color);
+    #[test]
+    fn either_winding_draws_the_same_pixels() {
+        let corners = [(-7.3, -2.1), (6.2, -4.8), (1.4, 8.9)];
+        let flipped = [corners[0], corners[2], corners[1]];
+        let mut counterclockwise = vec![];
+        rasterize_triangle(40, 40, corners, |x, y, w| counterclockwise.push((x, y, w)));
+        let mut clockwise = vec![];
+        rasterize_triangle(40, 40, flipped, |x, y, w| clockwise.push((x, y, [w[0], w[2], w[1]])));
+        assert!(!counterclockwise.is_empty());
+        assert_eq!(counterclockwise.len(), clockwise.len());
+        for ((x0, y0, w0), (x1, y1, w1)) in counterclockwise.iter().zip(&clockwise) {
+            assert_eq!((x0, y0), (x1, y1));
+            for k in 0..3 {
+                assert!((w0[k] - w1[k]).abs() < 1e-9);
+            }
         }
-            
     }
-
 }
-
-
@@ -0,0 +1,159 @@
+use crate::math::{Vector3, Vector4, Matrix4, vec4_dot, col_mat4_mul};
+
+//the bounds of clip space: near, left, right, bottom, top and far. A point
+//p is inside a plane when vec4_dot(plane, p) >= 0.
+pub const CLIP_PLANES: [Vector4; 6] = [
+    [0.0, 0.0, 1.0, 0.0],
+    [1.0, 0.0, 0.0, 1.0],
+    [-1.0, 0.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0, 1.0],
+    [0.0, -1.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0, 1.0]
+];
+
+//fixed directions to look at a scene from, as in the views of a level
+//editor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParallelView {
+    //along +z, i.e. the camera's own view
+    Front,
+    //straight down, with +z pointing up the screen
+    Top,
+    //from +x towards -x
+    Side,
+    //down the diagonal, so the x, y and z axes are 120 degrees apart
+    Isometric
+}
+
+impl ParallelView {
+    //yaw and pitch as used by render_3d::Camera
+    fn angles(self) -> (f64, f64) {
+        use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+        match self {
+            ParallelView::Front => (0.0, 0.0),
+            ParallelView::Top => (0.0, -FRAC_PI_2),
+            ParallelView::Side => (-FRAC_PI_2, 0.0),
+            ParallelView::Isometric => (FRAC_PI_4, -(0.5f64.sqrt()).atan())
+        }
+    }
+
+    //turns camera space so that the view direction becomes +z
+    pub fn rotation(self) -> Matrix4 {
+        let (yaw, pitch) = self.angles();
+        let f = [yaw.sin() * pitch.cos(), pitch.sin(), yaw.cos() * pitch.cos()];
+        let r = [yaw.cos(), 0.0, -yaw.sin()];
+        //f x r
+        let u = [
+            f[1] * r[2] - f[2] * r[1],
+            f[2] * r[0] - f[0] * r[2],
+            f[0] * r[1] - f[1] * r[0]
+        ];
+        [
+            [r[0], u[0], f[0], 0.0],
+            [r[1], u[1], f[1], 0.0],
+            [r[2], u[2], f[2], 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ]
+    }
+}
+
+//turns camera space (x right, y up, looking down +z) into clip space.
+//After dividing by w, everything visible ends up with x and y between -1
+//and 1 and a depth between 0 (near plane) and 1 (far plane).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    //fov_y is the vertical field of view in radians, aspect is width over
+    //height; far can be infinite
+    Perspective {fov_y: f64, aspect: f64, near: f64, far: f64},
+    //width and height of the visible box, in camera space units
+    Orthographic {width: f64, height: f64, near: f64, far: f64},
+    //orthographic, looking at the camera space origin from one of the fixed
+    //directions; everything within `depth` of the origin along the view
+    //direction is visible, including what's behind the camera
+    Parallel {view: ParallelView, width: f64, height: f64, depth: f64}
+}
+
+impl Projection {
+    pub fn perspective(fov_y: f64, aspect: f64, near: f64, far: f64) -> Projection {
+        Projection::Perspective {fov_y, aspect, near, far}
+    }
+
+    pub fn orthographic(width: f64, height: f64, near: f64, far: f64) -> Projection {
+        Projection::Orthographic {width, height, near, far}
+    }
+
+    pub fn parallel(view: ParallelView, width: f64, height: f64, depth: f64) -> Projection {
+        Projection::Parallel {view, width, height, depth}
+    }
+
+    pub fn near(&self) -> f64 {
+        match *self {
+            Projection::Perspective {near, ..} | Projection::Orthographic {near, ..} => near,
+            Projection::Parallel {depth, ..} => -depth
+        }
+    }
+
+    pub fn far(&self) -> f64 {
+        match *self {
+            Projection::Perspective {far, ..} | Projection::Orthographic {far, ..} => far,
+            Projection::Parallel {depth, ..} => depth
+        }
+    }
+
+    //the direction the camera looks in, in camera space. None for a
+    //perspective projection, where every point is seen from the origin
+    //along a direction of its own.
+    pub fn view_direction(&self) -> Option<Vector3> {
+        match *self {
+            Projection::Perspective {..} => None,
+            Projection::Orthographic {..} => Some([0.0, 0.0, 1.0]),
+            //whatever the rotation turns into +z
+            Projection::Parallel {view, ..} => {
+                let r = view.rotation();
+                Some([r[0][2], r[1][2], r[2][2]])
+            }
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        match *self {
+            Projection::Perspective {fov_y, aspect, near, far} => {
+                let sy = 1.0 / (fov_y / 2.0).tan();
+                let sx = sy / aspect;
+                //depth is a*z + b over w = z
+                let (a, b) = if far.is_infinite() {
+                    (1.0, -near)
+                } else {
+                    (far / (far - near), -near * far / (far - near))
+                };
+                [
+                    [sx, 0.0, 0.0, 0.0],
+                    [0.0, sy, 0.0, 0.0],
+                    [0.0, 0.0, a, 1.0],
+                    [0.0, 0.0, b, 0.0]
+                ]
+            },
+            Projection::Orthographic {width, height, near, far} => [
+                [2.0 / width, 0.0, 0.0, 0.0],
+                [0.0, 2.0 / height, 0.0, 0.0],
+                [0.0, 0.0, 1.0 / (far - near), 0.0],
+                [0.0, 0.0, -near / (far - near), 1.0]
+            ],
+            Projection::Parallel {view, width, height, depth} => col_mat4_mul(
+                Projection::orthographic(width, height, -depth, depth).matrix(),
+                view.rotation()
+            )
+        }
+    }
+
+    //CLIP_PLANES pulled back into camera space
+    pub fn frustum_planes(&self) -> [Vector4; 6] {
+        let m = self.matrix();
+        CLIP_PLANES.map(|c| [
+            vec4_dot(c, m[0]),
+            vec4_dot(c, m[1]),
+            vec4_dot(c, m[2]),
+            vec4_dot(c, m[3])
+        ])
+    }
+}
@@ -1,7 +1,11 @@
-use crate::math::{Vector3, Vector4,  Matrix3, col_mat3_transform};
+use crate::math::{Vector3, Vector4,  Matrix3, col_mat3_transform, col_mat4_transform};
+use super::projection::Projection;
+use super::lighting::Light;
+use super::render_3d::TransparentPixel;
 use super::primitives::{draw_filled_triangle, draw_wireframe_triangle, draw_textured_triangle};
 use super::bitmaps::Bitmap;
-use super::colors::{Color, from_u8_rgb};
+use super::colors::{Color, from_u8_rgb, from_u8_rgba};
+use super::render_target::RenderTarget;
 use std::iter::zip;
 
 pub struct Viewport {
@@ -10,8 +14,17 @@ pub struct Viewport {
     pub viewport_width: f64,
     pub viewport_height: f64,
     pub distance_d: f64,
+    //used for 3D rendering, see project_vertex_3d
+    pub projection: Projection,
+    //in world space, see Instance::shading
+    pub lights: Vec<Light>,
     pub background_color: Color,
+    //1 - normalized depth, so bigger is closer and 0 means nothing was
+    //drawn there yet
     pub depth_buffer: Vec<f64>,
+    //pixels of transparent triangles from every instance drawn so far,
+    //waiting for draw_transparent
+    pub transparent: Vec<TransparentPixel>,
     pub screen: Bitmap
 }
 
@@ -24,8 +37,17 @@ impl Viewport {
             viewport_width,
             viewport_height,
             distance_d,
+            //the same view as project_vertex gives, but with a depth range
+            projection: Projection::perspective(
+                2.0 * (viewport_height / 2.0 / distance_d).atan(),
+                viewport_width / viewport_height,
+                0.1,
+                1000.0
+            ),
+            lights: vec![],
             background_color,
             depth_buffer: vec![0.0; canvas_width*canvas_height], 
+            transparent: vec![],
             screen: Bitmap {
                 width: canvas_width,
                 height: canvas_height,
@@ -39,6 +61,46 @@ impl Viewport {
             *pixel = self.background_color;
             *depth = 0.0;
         }
+        self.transparent.clear();
+    }
+
+    //blends the queued transparent pixels over the rest of the scene,
+    //farthest first, leaving out the ones that ended up behind something
+    //opaque. present does this, call it before reading `screen` otherwise.
+    pub fn draw_transparent(&mut self) {
+        let mut transparent = std::mem::take(&mut self.transparent);
+        //a stable sort, so pixels at the same depth keep the order they
+        //were drawn in
+        transparent.sort_by(|a, b| a.depth.total_cmp(&b.depth));
+        for pixel in &transparent {
+            if matches!(self.get_dbuff_val(pixel.x, pixel.y), Some(dval) if pixel.depth > *dval) {
+                pixel.blend_mode.draw(self, pixel.x, pixel.y, pixel.color);
+            }
+        }
+        //keep the allocation for the next frame
+        transparent.clear();
+        self.transparent = transparent;
+    }
+
+    //hands the finished frame over to the target and starts a fresh one
+    pub fn present<T: RenderTarget>(&mut self, target: &mut T) -> Result<(), T::Error> {
+        self.draw_transparent();
+        target.present(&self.screen)?;
+        self.clear_screen();
+        Ok(())
+    }
+
+    //draws and presents `frames` frames in a row, no window required;
+    //the closure gets the frame number so it can animate the scene
+    pub fn render_frames<T, F>(&mut self, target: &mut T, frames: usize, mut draw: F) -> Result<(), T::Error>
+    where T: RenderTarget,
+          F: FnMut(&mut Viewport, usize)
+    {
+        for frame in 0..frames {
+            draw(self, frame);
+            self.present(target)?;
+        }
+        Ok(())
     }
         
 
@@ -54,15 +116,33 @@ impl Viewport {
                                 v[1]*self.distance_d/v[2])
     }
 
-    pub fn project_vertex_3d(&self, v: Vector4) -> (isize, isize){
-        self.viewport_to_canvas(
-            v[0]*self.distance_d/(v[2]*v[3]),
-            v[1]*self.distance_d/(v[2]*v[3])
+    //camera space to canvas coordinates, along with the normalized depth
+    //(0 at the near plane, 1 at the far one) and the clip space w, which
+    //the rasterizers need for perspective correct interpolation
+    pub fn project_vertex_3d(&self, v: Vector4) -> ((f64, f64), f64, f64) {
+        self.clip_to_canvas(col_mat4_transform(self.projection.matrix(), v))
+    }
+
+    //the same for a point that's already in clip space. The canvas point
+    //isn't rounded, so rasterize_triangle can place it between pixels.
+    pub fn clip_to_canvas(&self, clip: Vector4) -> ((f64, f64), f64, f64) {
+        let w = clip[3];
+        (
+            (
+                clip[0] / w * (self.canvas_width / 2) as f64,
+                clip[1] / w * (self.canvas_height / 2) as f64
+            ),
+            clip[2] / w,
+            w
         )
     }
 
+    pub fn frustum_planes(&self) -> [Vector4; 6] {
+        self.projection.frustum_planes()
+    }
+
     pub fn get_dbuff_val(&self, x: isize, y: isize) -> Option<&f64> {
-        if x < (self.canvas_width/2) as isize && x > (self.canvas_width/2) as isize*-1 {
+        if x < (self.canvas_width/2) as isize && x > -((self.canvas_width/2) as isize) {
             let x = (self.canvas_width/2) as isize + x;
             let y = (self.canvas_height/2) as isize - y;
             return self.depth_buffer.get((y*self.canvas_width as isize + x) as usize); 
@@ -70,8 +150,26 @@ impl Viewport {
         None
     }
 
+    //grayscale picture of the depth buffer, closest point is white;
+    //untouched pixels stay fully transparent
+    pub fn depth_bitmap(&self) -> Bitmap {
+        let max = self.depth_buffer.iter().cloned().fold(0.0, f64::max);
+        Bitmap {
+            width: self.canvas_width,
+            height: self.canvas_height,
+            data: self.depth_buffer.iter().map(|val| {
+                if *val > 0.0 {
+                    let shade = (val / max * 255.0) as u8;
+                    from_u8_rgba(shade, shade, shade, 255)
+                } else {
+                    from_u8_rgba(0, 0, 0, 0)
+                }
+            }).collect()
+        }
+    }
+
     pub fn set_dbuff_val(&mut self, x: isize, y: isize, val: f64) {
-        if x < (self.canvas_width/2) as isize && x > (self.canvas_width/2) as isize*-1 {
+        if x < (self.canvas_width/2) as isize && x > -((self.canvas_width/2) as isize) {
             let x = (self.canvas_width/2) as isize + x;
             let y = (self.canvas_height/2) as isize - y;
             if let Some(pixel) = self.depth_buffer.get_mut((y*self.canvas_width as isize + x) as usize) {
@@ -1,27 +1,379 @@
 use crate::math::{Vector4, Vector3, Matrix4,
-                  col_mat4_transform, col_mat4_mul};
+                  col_mat4_transform, col_mat4_mul,
+                  vec3_add, vec3_sub, vec3_scale, vec3_len, vec3_inv_len, vec3_cross, vec3_dot, vec3_square_len, vec3_normalized, vec4_dot};
 use super::render_2d::{Viewport};
-use super::primitives::{draw_wireframe_triangle,
-                        draw_filled_triangle,
-                        draw_textured_triangle,
-                        Interpolate, interpolate,
-                        putpixel
-};
-use super::colors::{Color, from_u8_rgb};
+use super::lighting::{Light, Shading, Illumination};
+use super::projection::CLIP_PLANES;
+use super::primitives::{draw_line, rasterize_triangle, putpixel, getpixel};
+use super::colors::{Color, from_u8_rgb, from_u8_rgba, from_rgba_u8, mix_colors};
 use super::bitmaps::Bitmap;
+use super::sampler::{Sampler, Mipmaps};
+use super::shaders::{Uniforms, Corner, VertexShader, FragmentShader, SurfaceShader};
 use std::iter::zip;
+use std::ops::Range;
+use std::iter;
+use std::collections::HashMap;
 
 
 
+//a run of triangles, how they get culled and the shaders they get drawn
+//with
+type Batch<'a> = (Range<usize>, CullMode, &'a dyn VertexShader, &'a dyn FragmentShader);
+//position, normal and uv indices of a corner, see Model::compute_tangents
+type CornerKey = (usize, Option<usize>, Option<usize>);
+
+//a corner of a triangle in clip space, along with the varyings that have
+//to be interpolated when an edge gets cut (see draw_triangle)
+#[derive(Debug, Clone)]
+struct ClipVertex {
+    position: Vector4,
+    varyings: Vec<f64>
+}
+
+impl ClipVertex {
+    //the corner `t` of the way from this one to `other`
+    fn lerp(&self, other: &ClipVertex, t: f64) -> ClipVertex {
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        ClipVertex {
+            position: [0, 1, 2, 3].map(|i| lerp(self.position[i], other.position[i])),
+            varyings: zip(&self.varyings, &other.varyings).map(|(a, b)| lerp(*a, *b)).collect()
+        }
+    }
+}
+
+//rasterize_triangle's weights are linear on screen; attributes need them
+//to be linear in 1/w instead
+fn perspective_correct(weights: [f64; 3], inv_w: [f64; 3]) -> [f64; 3] {
+    let q = [weights[0] * inv_w[0], weights[1] * inv_w[1], weights[2] * inv_w[2]];
+    let sum = q[0] + q[1] + q[2];
+    if sum == 0.0 {weights} else {[q[0] / sum, q[1] / sum, q[2] / sum]}
+}
+
+//how fast something that's linear on screen changes along canvas x and y
+//across a triangle, given its values at the corners
+fn screen_gradient(points: [(f64, f64); 3], values: [f64; 3]) -> (f64, f64) {
+    let e1 = (points[1].0 - points[0].0, points[1].1 - points[0].1);
+    let e2 = (points[2].0 - points[0].0, points[2].1 - points[0].1);
+    let area = e1.0 * e2.1 - e1.1 * e2.0;
+    if area == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (d1, d2) = (values[1] - values[0], values[2] - values[0]);
+    ((d1 * e2.1 - d2 * e1.1) / area, (d2 * e1.0 - d1 * e2.0) / area)
+}
+
+//the tangent at a corner with the given normal: `u` (the direction u
+//increases in) made perpendicular to the normal, with w saying whether
+//the direction v increases in is cross(normal, tangent) or its opposite.
+//Zero when there's no such direction.
+pub fn tangent_frame(normal: Vector3, u: Vector3, v: Vector3) -> Vector4 {
+    let normal = if vec3_square_len(normal) > 0.0 {vec3_normalized(normal)} else {normal};
+    let tangent = vec3_sub(u, vec3_scale(normal, vec3_dot(normal, u)));
+    if vec3_square_len(tangent) == 0.0 {
+        return [0.0; 4];
+    }
+    let tangent = vec3_normalized(tangent);
+    let handedness = if vec3_dot(vec3_cross(normal, tangent), v) < 0.0 {-1.0} else {1.0};
+    [tangent[0], tangent[1], tangent[2], handedness]
+}
+
+//Sutherland-Hodgman against a single plane (see projection::CLIP_PLANES),
+//keeping the side with a positive distance. Each plane can add at most one
+//corner to the polygon.
+fn clip_polygon(polygon: Vec<ClipVertex>, plane: &Vector4) -> Vec<ClipVertex> {
+    let distance = |v: &ClipVertex| vec4_dot(*plane, v.position);
+    if polygon.iter().all(|v| distance(v) >= 0.0) {
+        return polygon;
+    }
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let (d0, d1) = (distance(current), distance(next));
+        if d0 >= 0.0 {
+            clipped.push(current.clone());
+        }
+        if (d0 >= 0.0) != (d1 >= 0.0) {
+            clipped.push(current.lerp(next, d0 / (d0 - d1)));
+        }
+    }
+    clipped
+}
+
+fn clip_to_planes(mut polygon: Vec<ClipVertex>, planes: &[Vector4]) -> Vec<ClipVertex> {
+    for plane in planes {
+        if polygon.len() < 3 {
+            break;
+        }
+        polygon = clip_polygon(polygon, plane);
+    }
+    polygon
+}
+
+//clips a triangle (in clip space) against `planes` and draws whatever is
+//left of it through the fragment shader, unless it faces the culled way
+fn draw_clipped(view: &mut Viewport,
+                uniforms: &Uniforms,
+                planes: &[Vector4],
+                triangle: usize,
+                corners: &[ClipVertex; 3],
+                cull_mode: CullMode,
+                fragment_shader: &dyn FragmentShader) {
+    if cull_mode.culls(corners[0].position, corners[1].position, corners[2].position) {
+        return;
+    }
+    let polygon = clip_to_planes(corners.to_vec(), planes);
+    let projected: Vec<((f64, f64), f64, f64)> = polygon.iter()
+        .map(|v| view.clip_to_canvas(v.position))
+        .collect();
+    for i in 2..polygon.len() {
+        draw_triangle(view,
+                      [projected[0], projected[i - 1], projected[i]],
+                      [&polygon[0].varyings, &polygon[i - 1].varyings, &polygon[i].varyings],
+                      fragment_shader.blend_mode(),
+                      |fragment| fragment_shader.shade(uniforms, triangle, fragment));
+    }
+}
+
+//how much of a bounding sphere lies within the view frustum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visibility {
+    Outside,
+    Inside,
+    //crosses one of the planes the triangles get clipped against
+    Partial
+}
+
+fn sphere_visibility(planes: &[Vector4; 6], center: Vector4, radius: f64) -> Visibility {
+    let mut visibility = Visibility::Inside;
+    for plane in planes {
+        let normal = [plane[0], plane[1], plane[2]];
+        //the far plane of an infinite projection doesn't bound anything
+        if vec3_square_len(normal) == 0.0 {
+            continue;
+        }
+        let distance = vec4_dot(*plane, center) * vec3_inv_len(normal);
+        if distance < -radius {
+            return Visibility::Outside;
+        }
+        if distance < radius {
+            visibility = Visibility::Partial;
+        }
+    }
+    visibility
+}
+
+//which triangles Instance::render skips; a triangle faces the camera when
+//its corners appear counterclockwise from the outside (the OBJ convention)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CullMode {
+    #[default]
+    Back,
+    Front,
+    Disabled
+}
+
+impl CullMode {
+    //takes the corners in clip space, before clipping. The determinant of
+    //their x, y and w is the winding on screen (times the w's), which works
+    //for any projection and for corners behind the camera.
+    fn culls(self, a: Vector4, b: Vector4, c: Vector4) -> bool {
+        let determinant = vec3_dot(
+            [a[0], a[1], a[3]],
+            vec3_cross([b[0], b[1], b[3]], [c[0], c[1], c[3]])
+        );
+        //seen from the front the corners go clockwise on screen
+        let facing = determinant < 0.0;
+        match self {
+            CullMode::Back => !facing,
+            CullMode::Front => facing,
+            CullMode::Disabled => false
+        }
+    }
+}
+
+//how the pixels of a triangle get combined with what's already on screen
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BlendMode {
+    //covers whatever is behind it
+    #[default]
+    Opaque,
+    //opaque, but pixels with an alpha below the cutoff (between 0 and 1)
+    //are left out, e.g. for leaves or fences
+    AlphaTest(f64),
+    //mixed with what's behind it by its alpha, e.g. for glass
+    AlphaBlend,
+    //added on top of what's behind it, scaled by its alpha, e.g. for fire
+    Additive
+}
+
+impl BlendMode {
+    //transparent pixels wait in Viewport::transparent until everything
+    //else is drawn, then go on farthest first (see
+    //Viewport::draw_transparent); they leave the depth buffer alone so
+    //they don't hide each other
+    pub fn is_transparent(self) -> bool {
+        matches!(self, BlendMode::AlphaBlend | BlendMode::Additive)
+    }
+
+    //draws a pixel that passed the depth test, returning whether its depth
+    //should be written too
+    pub fn draw(self, view: &mut Viewport, x: isize, y: isize, color: Color) -> bool {
+        let (r, g, b, a) = from_rgba_u8(color);
+        let alpha = a as f64 / 255.0;
+        let color = match self {
+            BlendMode::Opaque => color,
+            BlendMode::AlphaTest(cutoff) => {
+                if alpha < cutoff {
+                    return false;
+                }
+                color
+            },
+            BlendMode::AlphaBlend | BlendMode::Additive => {
+                let behind = match getpixel(&view.screen, x, y) {
+                    Some(behind) => behind,
+                    None => return false
+                };
+                let (br, bg, bb, ba) = from_rgba_u8(behind);
+                if self == BlendMode::AlphaBlend {
+                    //source over, as in Bitmap::draw_rectangle_on
+                    let (mr, mg, mb, _) = from_rgba_u8(mix_colors(behind, color, alpha));
+                    let coverage = (a as f64 + ba as f64 * (1.0 - alpha)).round() as u8;
+                    from_u8_rgba(mr, mg, mb, coverage)
+                } else {
+                    let add = |c: u8, behind: u8| (behind as f64 + c as f64 * alpha).min(255.0) as u8;
+                    from_u8_rgba(add(r, br), add(g, bg), add(b, bb), ba)
+                }
+            }
+        };
+        putpixel(&mut view.screen, x, y, color);
+        !self.is_transparent()
+    }
+}
+
+//a pixel of a transparent triangle, see Viewport::transparent
+#[derive(Debug, Clone, Copy)]
+pub struct TransparentPixel {
+    pub x: isize,
+    pub y: isize,
+    //as in Viewport::depth_buffer
+    pub depth: f64,
+    pub color: Color,
+    pub blend_mode: BlendMode
+}
+
 pub enum MaterialData<'texture> {
     Flat(Vec<Color>),
-    UV(&'texture Bitmap)
+    UV(&'texture Bitmap, Sampler),
+    //like UV, sampling from the mip chain when the sampler asks for it
+    //(e.g. Sampler::trilinear), so far away surfaces don't shimmer
+    MipmappedUV(&'texture Mipmaps, Sampler),
+    //one material for the whole model, e.g. to give it normal or specular
+    //maps
+    Material(&'texture Material),
+    //use the materials the model was loaded with (see Model::materials)
+    FromModel,
+    //custom effects: the vertex shader places every corner and the
+    //fragment shader colors every pixel, see the shaders module. The other
+    //variants are drawn the same way with a SurfaceShader; the instance's
+    //shading doesn't apply here.
+    Shaded(&'texture dyn VertexShader, &'texture dyn FragmentShader)
+}
+
+//a material as described by an MTL file
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    //Ka, Kd and Ks, each channel between 0 and 1. Ka scales the ambient
+    //lights; it starts out at 1 so that materials which don't set it are
+    //lit like everything else.
+    pub ambient: Vector3,
+    pub diffuse: Vector3,
+    pub specular: Vector3,
+    //Ns
+    pub shininess: f64,
+    //d, 1.0 is fully opaque
+    pub opacity: f64,
+    //map_Kd
+    pub diffuse_map: Option<Bitmap>,
+    //map_d, a greyscale map whose brightness (times its own alpha)
+    //multiplies the alpha of the surface
+    pub alpha_map: Option<Bitmap>,
+    //map_Ks, multiplies `specular` texel by texel
+    pub specular_map: Option<Bitmap>,
+    //norm (or map_Bump), a tangent space normal map with red along the
+    //direction u increases in and green along v; needs Model::uv_map. Like
+    //the specular map it's only used with Shading::Phong.
+    pub normal_map: Option<Bitmap>,
+    //how all of the maps get sampled
+    pub sampler: Sampler,
+    //how the material covers what's behind it; the alpha comes from
+    //`opacity` times that of diffuse_map and alpha_map
+    pub blend_mode: BlendMode,
+    //overrides Instance::cull_mode for the triangles using this material
+    pub cull_mode: Option<CullMode>,
+    //overrides Instance::shading
+    pub shading: Option<Shading>
+}
+
+impl Material {
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: [1.0, 1.0, 1.0],
+            diffuse: [1.0, 1.0, 1.0],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 0.0,
+            opacity: 1.0,
+            diffuse_map: None,
+            alpha_map: None,
+            specular_map: None,
+            normal_map: None,
+            sampler: Sampler::default(),
+            blend_mode: BlendMode::Opaque,
+            cull_mode: None,
+            shading: None
+        }
+    }
+
+    pub fn diffuse_color(&self) -> Color {
+        let channel = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        from_u8_rgba(
+            channel(self.diffuse[0]),
+            channel(self.diffuse[1]),
+            channel(self.diffuse[2]),
+            channel(self.opacity)
+        )
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::new("default")
+    }
+}
+
+//a run of consecutive triangles sharing one of the model's materials
+#[derive(Debug, Clone)]
+pub struct MaterialGroup {
+    pub material: usize,
+    pub triangles: Range<usize>
 }
+
+//a run of consecutive triangles in one OBJ smoothing group (the `s`
+//statement); group 0 means smoothing is off
+#[derive(Debug, Clone)]
+pub struct SmoothingGroup {
+    pub group: u32,
+    pub triangles: Range<usize>
+}
+
 #[derive(Debug)]
 pub struct PolygonData {
     pub vertex: [usize; 3],
-    pub normal: [usize; 3],
-    pub uv_coord: [usize; 3]
+    //indices into Model::vertex_normals and Model::uv_map, None for
+    //corners that don't have any
+    pub normal: [Option<usize>; 3],
+    pub uv_coord: [Option<usize>; 3]
 }
 
 #[derive(Debug)]
@@ -29,307 +381,471 @@ pub struct Model {
     pub vertices: Vec<Vector4>,
     pub uv_map: Vec<Vector3>,
     pub vertex_normals: Vec<Vector3>,
-    pub triangles: Vec<PolygonData>
+    pub triangles: Vec<PolygonData>,
+    pub materials: Vec<Material>,
+    //in triangle order, triangles not covered by any group use
+    //Material::default()
+    pub material_groups: Vec<MaterialGroup>,
+    //in triangle order, only used by compute_vertex_normals
+    pub smoothing_groups: Vec<SmoothingGroup>,
+    //a tangent for each corner of each triangle, for normal mapping (see
+    //compute_tangents). Empty means every triangle uses the tangent of its
+    //own face.
+    pub tangents: Vec<[Vector4; 3]>
 }
 
 impl Model {
+    //a cube from -1 to 1 on every axis with two triangles per face, wound
+    //so they face outwards. Each vertex has a single uv coordinate shared by
+    //the faces meeting at it, so a texture only roughly covers them; there
+    //are no normals.
+    pub fn cube() -> Model {
+        let faces = [
+            [0, 1, 2], [0, 2, 3], [4, 0, 3], [4, 3, 7], [5, 4, 7], [5, 7, 6],
+            [1, 5, 6], [1, 6, 2], [4, 5, 1], [4, 1, 0], [2, 6, 7], [2, 7, 3]
+        ];
+        Model {
+            vertices: vec![
+                [1.0, 1.0, 1.0, 1.0],
+                [-1.0, 1.0, 1.0, 1.0],
+                [-1.0, -1.0, 1.0, 1.0],
+                [1.0, -1.0, 1.0, 1.0],
+                [1.0, 1.0, -1.0, 1.0],
+                [-1.0, 1.0, -1.0, 1.0],
+                [-1.0, -1.0, -1.0, 1.0],
+                [1.0, -1.0, -1.0, 1.0]
+            ],
+            vertex_normals: vec![],
+            uv_map: vec![
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0]
+            ],
+            triangles: faces.iter()
+                .map(|vertex| PolygonData {vertex: *vertex, normal: [None; 3], uv_coord: vertex.map(Some)})
+                .collect(),
+            materials: vec![],
+            material_groups: vec![],
+            smoothing_groups: vec![],
+            tangents: vec![]
+        }
+    }
+
+    //the unit normal of every triangle, pointing to the side its corners
+    //appear counterclockwise from; degenerate triangles get a zero vector
+    pub fn face_normals(&self) -> Vec<Vector3> {
+        self.triangles.iter().map(|t| {
+            let n = self.triangle_normal(t);
+            if vec3_square_len(n) > 0.0 {vec3_normalized(n)} else {n}
+        }).collect()
+    }
+
+    fn corner_position(&self, t: &PolygonData, corner: usize) -> Vector3 {
+        let v = self.vertices[t.vertex[corner]];
+        [v[0] / v[3], v[1] / v[3], v[2] / v[3]]
+    }
+
+    //unnormalized, so its length is twice the triangle's area
+    //the corner's entry in uv_map, None if it has none
+    pub fn corner_uv(&self, t: &PolygonData, corner: usize) -> Option<Vector3> {
+        t.uv_coord[corner].and_then(|i| self.uv_map.get(i)).copied()
+    }
+
+    //the corner's entry in vertex_normals, None if it has none
+    pub fn corner_normal(&self, t: &PolygonData, corner: usize) -> Option<Vector3> {
+        t.normal[corner].and_then(|i| self.vertex_normals.get(i)).copied()
+    }
+
+    fn triangle_normal(&self, t: &PolygonData) -> Vector3 {
+        let p = |i: usize| self.corner_position(t, i);
+        vec3_cross(vec3_sub(p(1), p(0)), vec3_sub(p(2), p(0)))
+    }
+
+    //the angle between the two edges meeting at a corner
+    fn corner_angle(&self, t: &PolygonData, corner: usize) -> f64 {
+        let p = |i: usize| self.corner_position(t, (corner + i) % 3);
+        let (a, b) = (vec3_sub(p(1), p(0)), vec3_sub(p(2), p(0)));
+        let lengths = vec3_len(a) * vec3_len(b);
+        if lengths == 0.0 {0.0} else {(vec3_dot(a, b) / lengths).clamp(-1.0, 1.0).acos()}
+    }
+
+    //replaces vertex_normals (and the normal indices of every triangle)
+    //with normals averaged over the triangles around each vertex. Two
+    //triangles only get smoothed together if they're in the same smoothing
+    //group and meet at no more than `crease_angle` (in radians); triangles
+    //in group 0 stay flat. Triangles outside any smoothing group count as
+    //one more group of their own, so for models without `s` statements
+    //only the crease angle matters.
+    pub fn compute_vertex_normals(&mut self, crease_angle: f64) {
+        let mut groups = vec![None; self.triangles.len()];
+        for smoothing in &self.smoothing_groups {
+            for group in &mut groups[smoothing.triangles.clone()] {
+                *group = Some(smoothing.group);
+            }
+        }
+
+        //each triangle around a vertex counts by the angle of its corner
+        //there, so splitting a face into more triangles doesn't change the
+        //result
+        let unit = self.face_normals();
+        let mut touching = vec![vec![]; self.vertices.len()];
+        for (i, t) in self.triangles.iter().enumerate() {
+            for (corner, v) in t.vertex.iter().enumerate() {
+                touching[*v].push((i, vec3_scale(unit[i], self.corner_angle(t, corner))));
+            }
+        }
+
+        let min_cos = crease_angle.cos();
+        let mut normals: Vec<Vector3> = vec![];
+        //corners often end up with the same normal, keep just one copy
+        let mut known: HashMap<[u64; 3], usize> = HashMap::new();
+        for i in 0..self.triangles.len() {
+            for corner in 0..3 {
+                let vertex = self.triangles[i].vertex[corner];
+                let normal = if groups[i] == Some(0) {
+                    unit[i]
+                } else {
+                    let sum = touching[vertex].iter()
+                        .filter(|(j, _)| groups[*j] == groups[i] && vec3_dot(unit[i], unit[*j]) >= min_cos)
+                        .fold([0.0; 3], |sum, (_, weighted)| vec3_add(sum, *weighted));
+                    if vec3_square_len(sum) > 0.0 {vec3_normalized(sum)} else {unit[i]}
+                };
+                //adding 0.0 turns -0.0 into 0.0
+                let index = *known.entry(normal.map(|c| (c + 0.0).to_bits())).or_insert_with(|| {
+                    normals.push(normal);
+                    normals.len() - 1
+                });
+                self.triangles[i].normal[corner] = Some(index);
+            }
+        }
+        self.vertex_normals = normals;
+    }
+
+    //the directions u and v increase in across a triangle, in model space;
+    //None without uv coordinates that span an area
+    fn triangle_uv_axes(&self, t: &PolygonData) -> Option<(Vector3, Vector3)> {
+        let p = |i: usize| self.corner_position(t, i);
+        let uv = |i: usize| self.corner_uv(t, i);
+        let (uv0, uv1, uv2) = (uv(0)?, uv(1)?, uv(2)?);
+        let (e1, e2) = (vec3_sub(p(1), p(0)), vec3_sub(p(2), p(0)));
+        let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+        let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant == 0.0 {
+            return None;
+        }
+        let u = vec3_sub(vec3_scale(e1, dv2), vec3_scale(e2, dv1));
+        let v = vec3_sub(vec3_scale(e2, du1), vec3_scale(e1, du2));
+        Some((vec3_scale(u, 1.0 / determinant), vec3_scale(v, 1.0 / determinant)))
+    }
+
+    //the tangent of a single triangle, as used when `tangents` is empty
+    pub fn face_tangent(&self, t: &PolygonData) -> Vector4 {
+        match self.triangle_uv_axes(t) {
+            Some((u, v)) => tangent_frame(self.triangle_normal(t), u, v),
+            None => [0.0; 4]
+        }
+    }
+
+    //replaces `tangents` with ones worked out from uv_map. Corners sharing
+    //a position, normal and uv coordinates get the same tangent, averaged
+    //over the triangles around them, so call this once the normals are
+    //final (e.g. after compute_vertex_normals).
+    pub fn compute_tangents(&mut self) {
+        let mut sums: HashMap<CornerKey, (Vector3, Vector3)> = HashMap::new();
+        for t in &self.triangles {
+            if let Some((u, v)) = self.triangle_uv_axes(t) {
+                for corner in 0..3 {
+                    let key = (t.vertex[corner], t.normal[corner], t.uv_coord[corner]);
+                    let sum = sums.entry(key).or_insert(([0.0; 3], [0.0; 3]));
+                    *sum = (vec3_add(sum.0, u), vec3_add(sum.1, v));
+                }
+            }
+        }
+
+        let face_normals = self.face_normals();
+        self.tangents = zip(&self.triangles, face_normals).map(|(t, face_normal)| {
+            [0, 1, 2].map(|corner| {
+                let normal = self.corner_normal(t, corner).unwrap_or(face_normal);
+                match sums.get(&(t.vertex[corner], t.normal[corner], t.uv_coord[corner])) {
+                    Some((u, v)) => tangent_frame(normal, *u, *v),
+                    None => [0.0; 4]
+                }
+            })
+        }).collect();
+    }
+
+    //a sphere around all the vertices in model space, as (center, radius);
+    //not the tightest one, but cheap to find
+    pub fn bounding_sphere(&self) -> (Vector3, f64) {
+        if self.vertices.is_empty() {
+            return ([0.0; 3], 0.0);
+        }
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for v in &self.vertices {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(v[axis]);
+                max[axis] = max[axis].max(v[axis]);
+            }
+        }
+        let center = vec3_scale(vec3_add(min, max), 0.5);
+        let radius = self.vertices.iter()
+            .map(|v| vec3_len(vec3_sub([v[0], v[1], v[2]], center)))
+            .fold(0.0, f64::max);
+        (center, radius)
+    }
+
     pub fn render_wireframe(&self,
                             view : &mut Viewport,
                             camera: Matrix4
     ){
-        let mut projected : Vec<(isize, isize)> = Vec::with_capacity(self.vertices.len());
-        for v in &self.vertices {
-            projected.push(view.project_vertex_3d(col_mat4_transform(camera, *v)));
-        }
-        
+        let to_clip = col_mat4_mul(view.projection.matrix(), camera);
+        let transformed: Vec<Vector4> = self.vertices.iter()
+            .map(|v| col_mat4_transform(to_clip, *v))
+            .collect();
+
         for t in &self.triangles {
-            draw_wireframe_triangle(
-                &mut view.screen,
-                projected[t.vertex[0]],
-                projected[t.vertex[1]],
-                projected[t.vertex[2]],
-                from_u8_rgb(0, 255, 0));
-        }   
+            let corners = t.vertex.iter()
+                .map(|i| ClipVertex {position: transformed[*i], varyings: vec![]})
+                .collect();
+            let polygon = clip_to_planes(corners, &CLIP_PLANES);
+            let projected: Vec<(isize, isize)> = polygon.iter()
+                .map(|v| {
+                    let (x, y) = view.clip_to_canvas(v.position).0;
+                    (x.round() as isize, y.round() as isize)
+                })
+                .collect();
+            for (i, p) in projected.iter().enumerate() {
+                draw_line(
+                    &mut view.screen,
+                    *p,
+                    projected[(i + 1) % projected.len()],
+                    from_u8_rgb(0, 255, 0));
+            }
+        }
     }
 }
 
-use std::mem;
+//a pixel of a triangle being drawn by draw_triangle
+pub struct Fragment<'a> {
+    pub x: isize,
+    pub y: isize,
+    //the triangle's varyings interpolated to this pixel
+    pub varyings: &'a [f64],
+    //perspective correct barycentric coordinates of the pixel
+    pub weights: [f64; 3],
+    //1/w at the pixel, and the canvas gradients of every varying over w
+    //followed by that of 1/w, for derivative
+    inv_w: f64,
+    gradients: &'a [(f64, f64)]
+}
+
+impl<'a> Fragment<'a> {
+    //how fast varying `i` changes along canvas x and y at this pixel, e.g.
+    //to pick a mip level
+    pub fn derivative(&self, i: usize) -> (f64, f64) {
+        let (g, q) = (self.gradients[i], self.gradients[self.varyings.len()]);
+        let value = self.varyings[i];
+        //the quotient rule, since the varying itself isn't linear on screen
+        ((g.0 - value * q.0) / self.inv_w, (g.1 - value * q.1) / self.inv_w)
+    }
+}
 
+//draws a depth tested triangle. The corners are given as returned by
+//Viewport::project_vertex_3d: canvas point, normalized depth and clip space
+//w. Each corner comes with the same number of varyings, any values to be
+//interpolated (perspective correctly) across the triangle: uv coordinates,
+//colors, normals and so on. `shade` gets every pixel that passes the depth
+//test and says what color it is, or None to leave it out; `blend_mode`
+//says how that color covers what's already on screen. Transparent pixels
+//are only queued up, see Viewport::draw_transparent.
+pub fn draw_triangle(view: &mut Viewport,
+                     corners: [((f64, f64), f64, f64); 3],
+                     varyings: [&[f64]; 3],
+                     blend_mode: BlendMode,
+                     mut shade: impl FnMut(&Fragment) -> Option<Color>) {
+    let count = varyings[0].len();
+    let points = corners.map(|c| c.0);
+    let inv_w = corners.map(|c| 1.0 / c.2);
+    //what goes into the depth buffer, see Viewport::depth_buffer
+    let depth = corners.map(|c| 1.0 - c.1);
+
+    //varyings over w and 1/w are linear on screen, so their gradients are
+    //the same all over the triangle
+    let gradients: Vec<(f64, f64)> = (0..count)
+        .map(|i| screen_gradient(points, [0, 1, 2].map(|k| varyings[k][i] * inv_w[k])))
+        .chain(iter::once(screen_gradient(points, inv_w)))
+        .collect();
+
+    let mut values = vec![0.0; count];
+    let (width, height) = (view.canvas_width, view.canvas_height);
+    rasterize_triangle(width, height, points, |x, y, weights| {
+        //depth is linear on screen, it doesn't need perspective correction
+        let d = (0..3).map(|k| depth[k] * weights[k]).sum::<f64>();
+        if !matches!(view.get_dbuff_val(x, y), Some(dval) if d > *dval) {
+            return;
+        }
+        let correct = perspective_correct(weights, inv_w);
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = (0..3).map(|k| varyings[k][i] * correct[k]).sum();
+        }
+        let fragment = Fragment {
+            x,
+            y,
+            varyings: &values,
+            weights: correct,
+            inv_w: (0..3).map(|k| inv_w[k] * weights[k]).sum(),
+            gradients: &gradients
+        };
+        if let Some(color) = shade(&fragment) {
+            if blend_mode.is_transparent() {
+                view.transparent.push(TransparentPixel {x, y, depth: d, color, blend_mode});
+            } else if blend_mode.draw(view, x, y, color) {
+                view.set_dbuff_val(x, y, d);
+            }
+        }
+    });
+}
+
+//draw_triangle with a texture. `texture` is a mip chain (see
+//Mipmaps::levels), a lone bitmap being a chain of one. `light`, if given,
+//gets the (perspective correct) barycentric coordinates of each pixel and
+//says how it's lit.
+#[allow(clippy::too_many_arguments)]
 pub fn draw_textured_polygon(view: &mut Viewport,
-                             p0: ((isize, isize), f64),
-                             p1: ((isize, isize), f64),
-                             p2: ((isize, isize), f64),
-                              uv0: (f64, f64),
-                              uv1: (f64, f64),
-                              uv2: (f64, f64),
-                              texture: &Bitmap){
-    
-    let c : [((isize, isize), f64); 3] = [p0, p1, p2];
-    let u : [(isize, isize); 3] =[
-        ((uv0.0 * texture.width as f64) as isize,
-         (uv0.1 * texture.height as f64) as isize),
-        ((uv1.0 * texture.width as f64) as isize,
-         (uv1.1 * texture.height as f64) as isize),
-        ((uv2.0 * texture.width as f64) as isize,
-         (uv2.1 * texture.height as f64) as isize),
-    ];
-
-    
-    
-    let mut lowest_point = 0;
-    let mut medium_point = 1;
-    let mut highest_point = 2;
-
-    //ensures the ordering
-    if c[medium_point].0.1 < c[lowest_point].0.1 {
-        mem::swap(&mut medium_point, &mut lowest_point);
-    }
-    if c[highest_point].0.1 < c[lowest_point].0.1 {
-        mem::swap(&mut highest_point, &mut lowest_point);
-    }
-    if c[highest_point].0.1 < c[medium_point].0.1 {
-        mem::swap(&mut highest_point, &mut medium_point);
-    }
-
-    let ((x0, y0), z0) = c[lowest_point];
-    let ((x1, y1), z1) = c[medium_point];
-    let ((x2, y2), z2) = c[highest_point];
-
-    let z0 = 1.0/z0 as f32; 
-    let z1 = 1.0/z1 as f32; 
-    let z2 = 1.0/z2 as f32;
-
-    //we want exactly one value of x for a value of y
-    let mut x0_to_x1 = interpolate(y0, x0 as f32, y1, x1 as f32);
-    let mut x1_to_x2 = interpolate(y1, x1 as f32, y2, x2 as f32);
-    let mut x0_to_x2 = interpolate(y0, x0 as f32, y2, x2 as f32);
-
-    //Concatenate the (vertically) short sides
-    x0_to_x1.end -= 1; //remove the last element to avoid repetition
-    let m = (x0_to_x1.len() + x1_to_x2.len() / 2) as usize;
-    let x0_to_x1_to_x2 = x0_to_x1.chain(x1_to_x2);
-    
-
-    //now we obtain the uv coordinates
-
-    let (u0, v0) = u[lowest_point];
-    let (u1, v1) = u[medium_point];
-    let (u2, v2) = u[highest_point];
-
-    //divide by the z coordinates to fix projection
-
-    let (u0, v0) = (u0 as f32 * z0, v0 as f32 * z0);
-    let (u1, v1) = (u1 as f32 * z1, v1 as f32 * z1);
-    let (u2, v2) = (u2 as f32 * z2, v2 as f32 * z2);
-    
-    let mut u0_to_u1 = interpolate(y0, u0, y1, u1);
-    let mut u1_to_u2 = interpolate(y1, u1, y2, u2);
-    let mut u0_to_u2 = interpolate(y0, u0, y2, u2);    
-
-    //since we can't assume anything about the uv mapping, we need to
-    //calculate the v values as well (I think?)
-    let mut v0_to_v1 = interpolate(y0, v0, y1, v1);
-    let mut v1_to_v2 = interpolate(y1, v1, y2, v2);
-    let mut v0_to_v2 = interpolate(y0, v0, y2, v2);
-
-    //Concatenate the (vertically) short sides
-    u0_to_u1.end -= 1;
-    
-    let u0_to_u1_to_u2 = u0_to_u1.chain(u1_to_u2);
-    
-    v0_to_v1.end -= 1;
-    
-    let v0_to_v1_to_v2 = v0_to_v1.chain(v1_to_v2);
-
-    let (x_left, x_right);
-
-    let (u_left, u_right);
-    let (v_left, v_right);
-
-
-//we want exactly one value of z for a value of y
-    let mut z0_to_z1 = interpolate(y0, z0, y1, z1);
-    let mut z1_to_z2 = interpolate(y1, z1, y2, z2);
-    let mut z0_to_z2 = interpolate(y0, z0, y2, z2);
-
-    //Concatenate the (vertically) short sides
-    z0_to_z1.end -= 1; //remove the last element to avoid repetition
-    let m = (z0_to_z1.len() + z1_to_z2.len() / 2) as usize; 
-    
-    let z0_to_z1_to_z2 = z0_to_z1.chain(z1_to_z2);
-    
-    let (z_left, z_right);
-
-    
-    
-    //we check the middle horizontal lines to see whether the vertically
-    //longest line is to the left or to the right of the other two
-    //@TODO: there should be a faster way to obtain these numbers
-    let (m_x0_x2, m_x0_x1_x2) = x0_to_x2.clone().zip(x0_to_x1_to_x2.clone()).nth(m).unwrap();
-
-    if x0_to_x2.len() > 1 &&  m_x0_x2 < m_x0_x1_x2 {
-        x_left = x0_to_x2.chain(Interpolate::empty());;
-        x_right = x0_to_x1_to_x2;
-
-        u_left = u0_to_u2.chain(Interpolate::empty());;
-        u_right = u0_to_u1_to_u2;
-        v_left = v0_to_v2.chain(Interpolate::empty());;
-        v_right = v0_to_v1_to_v2;
-
-        z_left = z0_to_z2.chain(Interpolate::empty());
-        z_right = z0_to_z1_to_z2;
-        
-    } else {
-        x_left = x0_to_x1_to_x2;
-        x_right = x0_to_x2.chain(Interpolate::empty());
-
-        u_left = u0_to_u1_to_u2;
-        u_right = u0_to_u2.chain(Interpolate::empty());
-        v_left = v0_to_v1_to_v2;
-        v_right = v0_to_v2.chain(Interpolate::empty());
-
-        z_left = z0_to_z1_to_z2;
-        z_right = z0_to_z2.chain(Interpolate::empty());
-    }
-    
-    
-    //(y, ((xl, xr), (ul, ur)), ((vl, vr), (zl, zr)))
-    //
-    for (y, ((((xl, xr), (ul, ur)), (vl, vr)), (zl, zr))) in (y0..y2)
-        .zip(x_left.zip(x_right)
-             .zip(u_left.zip(u_right))
-             .zip(v_left.zip(v_right))
-             .zip(z_left.zip(z_right))
-        ){
-            
-            //find the uv coordinates of each pixel in the y-line
-            let u_coords = interpolate(xl as isize, ul, xr as isize, ur);
-        
-            let v_coords = interpolate(xl as isize, vl, xr as isize, vr);
-
-            let z_coords = interpolate(xl as isize, zl, xr as isize, zr);
-
-            
-        let (xl, xr) = (xl as isize, xr as isize);
-            for ((x, (u, v)), z) in (xl..xr)
-                .zip(u_coords.zip(v_coords))
-                .zip(z_coords)
-            {
-                if matches!(view.get_dbuff_val(x, y),
-                            Some(dval)
-                            if z as f64 > *dval) {
-                
-                    //sample the uv coordinates from the texture
-                    let u = (u/z) as usize % texture.width;
-                    let v = (v/z) as usize % texture.height;
-           
-                    let color = texture.data[v*texture.width+u];
-                    putpixel(&mut view.screen, x, y, color);
-                    view.set_dbuff_val(x, y, z as f64);
-                }
+                             p0: ((f64, f64), f64, f64),
+                             p1: ((f64, f64), f64, f64),
+                             p2: ((f64, f64), f64, f64),
+                             uv0: (f64, f64),
+                             uv1: (f64, f64),
+                             uv2: (f64, f64),
+                             texture: &[Bitmap],
+                             sampler: &Sampler,
+                             opacity: f64,
+                             blend_mode: BlendMode,
+                             light: Option<&dyn Fn([f64; 3]) -> Illumination>){
+    let uv = [uv0, uv1, uv2].map(|uv| [uv.0, uv.1]);
+    draw_triangle(view, [p0, p1, p2], [&uv[0], &uv[1], &uv[2]], blend_mode, |fragment| {
+        //sample the uv coordinates from the texture
+        let uv = (fragment.varyings[0], fragment.varyings[1]);
+        let mut color = sampler.sample_with_derivatives(texture, uv, fragment.derivative(0), fragment.derivative(1));
+        if opacity < 1.0 {
+            let (r, g, b, a) = from_rgba_u8(color);
+            color = from_u8_rgba(r, g, b, (a as f64 * opacity).round() as u8);
         }
-            
-    }
+        if let Some(light) = light {
+            color = light(fragment.weights).apply(color);
+        }
+        Some(color)
+    });
+}
 
+
+//draw_triangle in a single color, the alpha coming from `color`; takes
+//the light the same way as draw_textured_polygon
+pub fn draw_filled_polygon(view: &mut Viewport, p0: ((f64, f64), f64, f64), p1: ((f64, f64), f64, f64), p2: ((f64, f64), f64, f64), color: Color,
+                           blend_mode: BlendMode,
+                           light: Option<&dyn Fn([f64; 3]) -> Illumination>){
+    draw_triangle(view, [p0, p1, p2], [&[], &[], &[]], blend_mode, |fragment| Some(match light {
+        Some(light) => light(fragment.weights).apply(color),
+        None => color
+    }));
 }
 
 
-pub fn draw_filled_polygon(view: &mut Viewport, p0: ((isize, isize), f64), p1: ((isize, isize), f64), p2: ((isize, isize), f64), color: Color){
+//a camera looking down its own +z axis with +y up, which is how
+//Projection expects camera space to be laid out. The orientation is
+//kept as yaw (around the world y axis, 0 looks along +z) and pitch (up is
+//positive), so there's no roll.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: Vector3,
+    pub yaw: f64,
+    pub pitch: f64
+}
 
-    let c : [((isize, isize), f64); 3] = [p0, p1, p2];
-    let mut lowest_point = 0;
-    let mut medium_point = 1;
-    let mut highest_point = 2;
+//keeps the camera from flipping over when looking straight up or down
+const MAX_PITCH: f64 = std::f64::consts::FRAC_PI_2 - 0.001;
 
-    //ensures the ordering
-    if c[medium_point].0.1 < c[lowest_point].0.1 {
-        mem::swap(&mut medium_point, &mut lowest_point);
-    }
-    if c[highest_point].0.1 < c[lowest_point].0.1 {
-        mem::swap(&mut highest_point, &mut lowest_point);
+impl Camera {
+    pub fn new(position: Vector3) -> Camera {
+        Camera {position, yaw: 0.0, pitch: 0.0}
     }
-    if c[highest_point].0.1 < c[medium_point].0.1 {
-        mem::swap(&mut highest_point, &mut medium_point);
+
+    pub fn forward(&self) -> Vector3 {
+        [
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos()
+        ]
     }
-    
-    let ((x0, y0), z0) = c[lowest_point];
-    let ((x1, y1), z1) = c[medium_point];
-    let ((x2, y2), z2) = c[highest_point];
 
-    let z0 = 1.0/z0; 
-    let z1 = 1.0/z1; 
-    let z2 = 1.0/z2; 
+    pub fn right(&self) -> Vector3 {
+        [self.yaw.cos(), 0.0, -self.yaw.sin()]
+    }
 
-    //we want exactly one value of x for a value of y
-    let mut x0_to_x1 = interpolate(y0, x0 as f32, y1, x1 as f32);
-    let mut x1_to_x2 = interpolate(y1, x1 as f32, y2, x2 as f32);
-    let mut x0_to_x2 = interpolate(y0, x0 as f32, y2, x2 as f32);
+    pub fn up(&self) -> Vector3 {
+        vec3_cross(self.forward(), self.right())
+    }
 
-    //Concatenate the (vertically) short sides
-    x0_to_x1.end -= 1; //remove the last element to avoid repetition
-    let m = (x0_to_x1.len() + x1_to_x2.len() / 2) as usize;
-    //ok, I think I get it
-    //len should never be in the negative.
-    
-    let x0_to_x1_to_x2 = x0_to_x1.chain(x1_to_x2);
-    
-    let (x_left, x_right);
+    pub fn look_at(&mut self, target: Vector3) {
+        let direction = vec3_sub(target, self.position);
+        if vec3_square_len(direction) == 0.0 {
+            return;
+        }
+        self.yaw = direction[0].atan2(direction[2]);
+        self.pitch = direction[1].atan2(direction[0].hypot(direction[2])).clamp(-MAX_PITCH, MAX_PITCH);
+    }
 
+    //FPS style mouse look, in radians
+    pub fn turn(&mut self, yaw: f64, pitch: f64) {
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
 
-    //we want exactly one value of z for a value of y
-    let mut z0_to_z1 = interpolate(y0, z0 as f32, y1, z1 as f32);
-    let mut z1_to_z2 = interpolate(y1, z1 as f32, y2, z2 as f32);
-    let mut z0_to_z2 = interpolate(y0, z0 as f32, y2, z2 as f32);
+    //FPS style movement: forward and right stay level with the ground no
+    //matter where the camera looks, up is along the world y axis
+    pub fn walk(&mut self, forward: f64, right: f64, up: f64) {
+        let level_forward = [self.yaw.sin(), 0.0, self.yaw.cos()];
+        self.position = vec3_add(self.position, vec3_scale(level_forward, forward));
+        self.position = vec3_add(self.position, vec3_scale(self.right(), right));
+        self.position[1] += up;
+    }
 
-    //Concatenate the (vertically) short sides
-    z0_to_z1.end -= 1; //remove the last element to avoid repetition
-    let m = (z0_to_z1.len() + z1_to_z2.len() / 2) as usize; 
-    
-    let z0_to_z1_to_z2 = z0_to_z1.chain(z1_to_z2);
-    
-    let (z_left, z_right);
+    //moves along the view direction, e.g. to zoom in on an orbit target
+    pub fn dolly(&mut self, distance: f64) {
+        self.position = vec3_add(self.position, vec3_scale(self.forward(), distance));
+    }
 
-    
-    //we check the middle horizontal lines to see whether the vertically
-    //longest line is to the left or to the right of the other two
-    //@TODO: there should be a faster way to obtain these numbers
-    
-    let (m_x0_x2, m_x0_x1_x2) = x0_to_x2.clone().zip(x0_to_x1_to_x2.clone()).nth(m).unwrap();
-    //@TODO: do I really need to do this weird stuff with chaining?
-    if x0_to_x2.len() > 1 && m_x0_x2 < m_x0_x1_x2 {
-        x_left = x0_to_x2.chain(Interpolate::empty());
-        x_right = x0_to_x1_to_x2;
-        
-        z_left = z0_to_z2.chain(Interpolate::empty());
-        z_right = z0_to_z1_to_z2;
-    } else {
-        x_left = x0_to_x1_to_x2;
-        x_right = x0_to_x2.chain(Interpolate::empty());
-        
-        z_left = z0_to_z1_to_z2;
-        z_right = z0_to_z2.chain(Interpolate::empty());
-    }
-
-    
-    for (y, ((xl, xr), (zl, zr))) in (y0..y2)
-        .zip(x_left.zip(x_right)
-             .zip(z_left.zip(z_right))
-        ){
-            let (xl, xr) = (xl as isize, xr as isize);
-            for (x, z) in (xl..xr).zip(interpolate(xl, zl, xr, zr)) {
-                if matches!(view.get_dbuff_val(x, y),
-                            Some(dval)
-                            if z as f64 > *dval) {
-                    putpixel(&mut view.screen, x, y, color);
-                    view.set_dbuff_val(x, y, z as f64);
+    //swings the camera around `target` at its current distance, leaving it
+    //looking at the target
+    pub fn orbit(&mut self, target: Vector3, yaw: f64, pitch: f64) {
+        let distance = vec3_len(vec3_sub(target, self.position));
+        self.look_at(target);
+        self.turn(yaw, pitch);
+        self.position = vec3_sub(target, vec3_scale(self.forward(), distance));
+    }
 
-                }
-                
-        }
-    }  
+    //the world to camera space matrix to pass to Instance::render and
+    //Model::render_wireframe
+    pub fn view_matrix(&self) -> Matrix4 {
+        let (r, u, f) = (self.right(), self.up(), self.forward());
+        let p = self.position;
+        [
+            [r[0], u[0], f[0], 0.0],
+            [r[1], u[1], f[1], 0.0],
+            [r[2], u[2], f[2], 0.0],
+            [-vec3_dot(r, p), -vec3_dot(u, p), -vec3_dot(f, p), 1.0]
+        ]
+    }
 }
 
-
 pub struct Instance<'model, 'texture> {
     pub model: &'model Model,
     pub scale: f64,
@@ -339,7 +855,10 @@ pub struct Instance<'model, 'texture> {
     pub x: f64,
     pub y: f64,
     pub z: f64,
-    pub material: MaterialData<'texture>
+    pub material: MaterialData<'texture>,
+    pub cull_mode: CullMode,
+    //how the lights in Viewport::lights affect the instance
+    pub shading: Shading
 }
 
 
@@ -391,68 +910,145 @@ impl<'model, 'texture> Instance<'model, 'texture>{
             ]
         );
 
-        //translation
+        //translation, applied after scaling and rotating
         transform_matrix = col_mat4_mul(
-            transform_matrix,
             [
-                [1.0, 0.0, 0.0, self.x],
-                [0.0, 1.0, 0.0, self.y],
-                [0.0, 0.0, 1.0, self.z],
-                [0.0, 0.0, 0.0, 1.0]
-            ]
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [self.x, self.y, self.z, 1.0]
+            ],
+            transform_matrix
         );
-        let mut projected : Vec<((isize, isize), f64)> = Vec::with_capacity(self.model.vertices.len());
-        for v in &self.model.vertices {
-            let vertex = col_mat4_transform(
-                        col_mat4_mul(
-                            camera,
-                            transform_matrix
-                        ),
-                        *v
-            );
-
-            let z = vertex[2];
-            projected.push(
-                (view.project_vertex_3d(
-                    vertex
-                ), z)
-            );
-        }
-        
+        let to_camera = col_mat4_mul(camera, transform_matrix);
 
-        match &self.material {
-            MaterialData::UV(texture) => {
-                for t in &self.model.triangles{
-                    draw_textured_polygon(
-                        view,
-                        projected[t.vertex[0]],
-                        projected[t.vertex[1]],
-                        projected[t.vertex[2]],
-                        (self.model.uv_map[t.uv_coord[0]][0],
-                         self.model.uv_map[t.uv_coord[0]][1]),
-                        (self.model.uv_map[t.uv_coord[1]][0],
-                         self.model.uv_map[t.uv_coord[1]][1]),
-                        (self.model.uv_map[t.uv_coord[2]][0],
-                         self.model.uv_map[t.uv_coord[2]][1]),
-                        texture
-                    )
+        //the built in shaders for each run of triangles, with the culling
+        //they get
+        let count = self.model.triangles.len();
+        let default = Material::default();
+        let surfaces: Vec<(Range<usize>, CullMode, SurfaceShader)> = match &self.material {
+            MaterialData::Flat(colors) =>
+                vec![(0..colors.len().min(count), self.cull_mode, SurfaceShader::flat(colors, self.shading))],
+            MaterialData::UV(texture, sampler) =>
+                vec![(0..count, self.cull_mode, SurfaceShader::uv(texture, *sampler, self.shading))],
+            MaterialData::MipmappedUV(mipmaps, sampler) =>
+                vec![(0..count, self.cull_mode, SurfaceShader::mipmapped(mipmaps, *sampler, self.shading))],
+            MaterialData::Material(material) => vec![self.material_surface(0..count, material)],
+            MaterialData::FromModel => {
+                let mut surfaces = vec![];
+                let mut next = 0;
+                for group in &self.model.material_groups {
+                    surfaces.push(self.material_surface(next..group.triangles.start, &default));
+                    surfaces.push(self.material_surface(group.triangles.clone(), &self.model.materials[group.material]));
+                    next = group.triangles.end;
                 }
-                
+                surfaces.push(self.material_surface(next..count, &default));
+                surfaces
             },
-            MaterialData::Flat(colors) => {        
-                for (color, t) in zip(colors, &self.model.triangles) {
-                    
-                    draw_filled_polygon(
-                        view,
-                        projected[t.vertex[0]],
-                        projected[t.vertex[1]],
-                        projected[t.vertex[2]],
-                        *color)
-                }
+            MaterialData::Shaded(..) => vec![]
+        };
+        let batches: Vec<Batch> = match self.material {
+            MaterialData::Shaded(vertex_shader, fragment_shader) =>
+                vec![(0..count, self.cull_mode, vertex_shader, fragment_shader)],
+            _ => surfaces.iter()
+                .map(|(triangles, cull_mode, shader)| (triangles.clone(), *cull_mode, shader as &dyn VertexShader, shader as &dyn FragmentShader))
+                .collect()
+        };
+
+        //skip instances that are entirely out of view, and the per-triangle
+        //clipping for ones that are entirely in it; that takes knowing where
+        //the vertex shaders put the vertices
+        let planes: &[Vector4] = if batches.iter().all(|(_, _, vertex_shader, _)| vertex_shader.stays_in_bounds()) {
+            let planes = view.frustum_planes();
+            let (center, radius) = self.model.bounding_sphere();
+            let center = col_mat4_transform(to_camera, [center[0], center[1], center[2], 1.0]);
+            let stretch = (0..3)
+                .map(|i| vec3_len([to_camera[i][0], to_camera[i][1], to_camera[i][2]]))
+                .fold(0.0, f64::max);
+            match sphere_visibility(&planes, center, radius * stretch) {
+                Visibility::Outside => return,
+                Visibility::Inside => &[],
+                Visibility::Partial => &CLIP_PLANES
             }
+        } else {&CLIP_PLANES};
+
+        let lights: Vec<Light> = view.lights.iter().map(|light| light.transform(camera)).collect();
+        let uniforms = Uniforms {
+            model: self.model,
+            to_camera,
+            projection: view.projection.matrix(),
+            view_direction: view.projection.view_direction(),
+            lights: &lights
+        };
 
+        //every corner of every triangle through its vertex shader
+        let corners: Vec<Vec<[ClipVertex; 3]>> = batches.iter()
+            .map(|(triangles, _, vertex_shader, _)| triangles.clone()
+                .map(|triangle| [0, 1, 2].map(|corner| {
+                    let mut varyings = vec![];
+                    let position = vertex_shader.shade(&Corner {uniforms: &uniforms, triangle, corner}, &mut varyings);
+                    ClipVertex {position, varyings}
+                }))
+                .collect())
+            .collect();
+
+        //transparent triangles only get queued up here, so they end up over
+        //the opaque ones of every instance (see Viewport::draw_transparent)
+        for (batch, (triangles, cull_mode, _, fragment_shader)) in batches.iter().enumerate() {
+            for (triangle, corners) in triangles.clone().zip(&corners[batch]) {
+                draw_clipped(view, &uniforms, planes, triangle, corners, *cull_mode, *fragment_shader);
+            }
         }
     }
 
+    //SurfaceShader::material, culled the way the material says
+    fn material_surface<'a>(&self, triangles: Range<usize>, material: &'a Material) -> (Range<usize>, CullMode, SurfaceShader<'a>) {
+        (triangles, material.cull_mode.unwrap_or(self.cull_mode), SurfaceShader::material(material, self.shading))
+    }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance<'a>(model: &'a Model, z: f64, scale: f64, material: MaterialData<'a>) -> Instance<'a, 'a> {
+        Instance {
+            model, scale, r_pitch: 0.0, r_yaw: 0.0, r_roll: 0.0, x: 0.0, y: 0.0, z,
+            material, cull_mode: CullMode::Back, shading: Shading::Unlit
+        }
+    }
+
+    //half see-through blue glass in front of an opaque red wall comes out
+    //purple whichever of them gets rendered first
+    #[test]
+    fn transparent_pixels_wait_for_every_instance() {
+        let cube = Model::cube();
+        let mut glass = Material::new("glass");
+        glass.diffuse = [0.0, 0.0, 1.0];
+        glass.opacity = 0.5;
+        glass.blend_mode = BlendMode::AlphaBlend;
+        let camera = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 5.0, 1.0]
+        ];
+
+        let mut centers = vec![];
+        for glass_first in [true, false] {
+            let mut view = Viewport::new(64, 64, 1.0, 1.0, 1.0, from_u8_rgb(255, 255, 255));
+            let glass = instance(&cube, 0.0, 1.0, MaterialData::Material(&glass));
+            let wall = instance(&cube, 4.0, 3.0, MaterialData::Flat(vec![from_u8_rgb(255, 0, 0); 12]));
+            let order = if glass_first {[&glass, &wall]} else {[&wall, &glass]};
+            for instance in order {
+                instance.render(&mut view, camera);
+            }
+            view.draw_transparent();
+            centers.push(getpixel(&view.screen, 0, 0).unwrap());
+        }
+        assert_eq!(centers[0], centers[1]);
+        let (r, g, b, _) = from_rgba_u8(centers[0]);
+        assert!((126..=128).contains(&r) && g == 0 && (127..=129).contains(&b), "{:?}", (r, g, b));
+    }
+}
@@ -0,0 +1,97 @@
+use std::convert::Infallible;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use super::bitmaps::Bitmap;
+use super::save_tga::{save_bitmap_as_tga, TgaOptions};
+use super::netpbm::{write_netpbm, NetpbmFormat};
+
+//anything a finished frame can be handed to: a window, a list of bitmaps
+//kept in memory, a sequence of files on disk...
+pub trait RenderTarget {
+    type Error;
+    fn present(&mut self, frame: &Bitmap) -> Result<(), Self::Error>;
+}
+
+//keeps every presented frame in memory, so the renderer can be run
+//without any window system (tests, CI, batch jobs)
+#[derive(Default)]
+pub struct Offscreen {
+    pub frames: Vec<Bitmap>
+}
+
+impl Offscreen {
+    pub fn new() -> Offscreen {
+        Offscreen {
+            frames: Vec::new()
+        }
+    }
+
+    pub fn last_frame(&self) -> Option<&Bitmap> {
+        self.frames.last()
+    }
+
+    pub fn into_frames(self) -> Vec<Bitmap> {
+        self.frames
+    }
+}
+
+impl RenderTarget for Offscreen {
+    type Error = Infallible;
+    fn present(&mut self, frame: &Bitmap) -> Result<(), Infallible> {
+        self.frames.push(frame.clone());
+        Ok(())
+    }
+}
+
+//writes every presented frame to `<directory>/<prefix><frame number>.tga`
+pub struct TgaFrames {
+    pub directory: PathBuf,
+    pub prefix: String,
+    pub options: TgaOptions,
+    pub next_frame: usize
+}
+
+impl TgaFrames {
+    pub fn new<P: Into<PathBuf>>(directory: P, prefix: &str) -> TgaFrames {
+        TgaFrames {
+            directory: directory.into(),
+            prefix: prefix.to_string(),
+            options: TgaOptions::default(),
+            next_frame: 0
+        }
+    }
+}
+
+impl RenderTarget for TgaFrames {
+    type Error = io::Error;
+    fn present(&mut self, frame: &Bitmap) -> io::Result<()> {
+        let path = self.directory.join(format!("{}{:05}.tga", self.prefix, self.next_frame));
+        save_bitmap_as_tga(frame, path, self.options)?;
+        self.next_frame += 1;
+        Ok(())
+    }
+}
+
+//streams the frames one after another into a writer, e.g. the stdin of
+//`ffmpeg -f image2pipe -c:v ppm -i - out.mp4`
+pub struct NetpbmStream<W: Write> {
+    pub writer: W,
+    pub format: NetpbmFormat
+}
+
+impl<W: Write> RenderTarget for NetpbmStream<W> {
+    type Error = io::Error;
+    fn present(&mut self, frame: &Bitmap) -> io::Result<()> {
+        write_netpbm(frame, &mut self.writer, self.format)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "window")]
+impl RenderTarget for minifb::Window {
+    type Error = minifb::Error;
+    fn present(&mut self, frame: &Bitmap) -> Result<(), minifb::Error> {
+        self.update_with_buffer(&frame.data, frame.width, frame.height)
+    }
+}
@@ -0,0 +1,189 @@
+use super::bitmaps::Bitmap;
+use super::colors::{Color, mix_colors, from_rgba_u8, from_u8_rgba};
+
+//what sampling a texture without any texels gives
+const TRANSPARENT: Color = 0;
+
+//how a texel gets picked from the ones around a uv coordinate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    //the texel the coordinate falls in
+    #[default]
+    Nearest,
+    //the four texels closest to the coordinate, weighted by distance
+    Bilinear
+}
+
+//how the level of a mip chain gets picked, see Mipmaps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MipmapFilter {
+    //always use the full size texture
+    #[default]
+    None,
+    //the level closest in size to the pixel's footprint
+    Nearest,
+    //a mix of the two levels around it; together with Filter::Bilinear
+    //this is trilinear filtering
+    Linear
+}
+
+//what happens to uv coordinates outside 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Wrap {
+    //the texture tiles
+    #[default]
+    Repeat,
+    //the texels along the edges stretch out forever
+    ClampToEdge,
+    //the texture tiles, with every other copy flipped
+    MirroredRepeat
+}
+
+impl Wrap {
+    //maps any texel index into 0..size
+    fn texel(self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::ClampToEdge => i.clamp(0, size - 1),
+            Wrap::MirroredRepeat => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {i} else {2 * size - 1 - i}
+            }
+        };
+        i as usize
+    }
+}
+
+//reads colors out of a texture at uv coordinates: u runs along the rows of
+//`data` and v across them, the whole bitmap covering 0 to 1 both ways
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    pub mipmap: MipmapFilter
+}
+
+impl Sampler {
+    pub fn new(filter: Filter, wrap: Wrap) -> Sampler {
+        Sampler {filter, wrap_u: wrap, wrap_v: wrap, mipmap: MipmapFilter::None}
+    }
+
+    pub fn trilinear(wrap: Wrap) -> Sampler {
+        Sampler {mipmap: MipmapFilter::Linear, ..Sampler::new(Filter::Bilinear, wrap)}
+    }
+
+    pub fn sample(&self, texture: &Bitmap, uv: (f64, f64)) -> Color {
+        //nothing to wrap around, e.g. a zero size image file
+        if texture.width == 0 || texture.height == 0 {
+            return TRANSPARENT;
+        }
+        let (x, y) = (uv.0 * texture.width as f64, uv.1 * texture.height as f64);
+        let texel = |x: isize, y: isize| {
+            texture.data[self.wrap_v.texel(y, texture.height) * texture.width + self.wrap_u.texel(x, texture.width)]
+        };
+        match self.filter {
+            Filter::Nearest => texel(x.floor() as isize, y.floor() as isize),
+            Filter::Bilinear => {
+                //texel centers sit halfway between the integer coordinates
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as isize, y0 as isize);
+                let top = mix_colors(texel(x0, y0), texel(x0 + 1, y0), tx);
+                let bottom = mix_colors(texel(x0, y0 + 1), texel(x0 + 1, y0 + 1), tx);
+                mix_colors(top, bottom, ty)
+            }
+        }
+    }
+    //samples the levels of a mip chain (see Mipmaps::levels), `lod` being
+    //log2 of how many level 0 texels a pixel spans
+    pub fn sample_levels(&self, levels: &[Bitmap], uv: (f64, f64), lod: f64) -> Color {
+        let last = match levels.len().checked_sub(1) {
+            Some(last) => last,
+            None => return TRANSPARENT
+        };
+        if last == 0 || lod <= 0.0 {
+            return self.sample(&levels[0], uv);
+        }
+        let lod = lod.min(last as f64);
+        match self.mipmap {
+            MipmapFilter::None => self.sample(&levels[0], uv),
+            MipmapFilter::Nearest => self.sample(&levels[lod.round() as usize], uv),
+            MipmapFilter::Linear => {
+                let level = lod.floor() as usize;
+                let finer = self.sample(&levels[level], uv);
+                if level == last {
+                    return finer;
+                }
+                mix_colors(finer, self.sample(&levels[level + 1], uv), lod - level as f64)
+            }
+        }
+    }
+
+    //sample_levels with the level picked from how fast u and v change along
+    //canvas x and y at the pixel (see render_3d::Fragment::derivative)
+    pub fn sample_with_derivatives(&self, levels: &[Bitmap], uv: (f64, f64), du: (f64, f64), dv: (f64, f64)) -> Color {
+        let lod = if levels.len() > 1 && self.mipmap != MipmapFilter::None {
+            level_of_detail(du, dv, &levels[0])
+        } else {0.0};
+        self.sample_levels(levels, uv, lod)
+    }
+}
+
+//log2 of how many texels of `texture` one pixel spans, which is the mip
+//level to sample
+fn level_of_detail(du: (f64, f64), dv: (f64, f64), texture: &Bitmap) -> f64 {
+    let (width, height) = (texture.width as f64, texture.height as f64);
+    (du.0 * width).hypot(dv.0 * height).max((du.1 * width).hypot(dv.1 * height)).log2()
+}
+
+//a texture followed by smaller and smaller copies of itself, so that far
+//away surfaces can be sampled from a copy about as detailed as the screen
+//can show instead of shimmering
+#[derive(Debug, Clone)]
+pub struct Mipmaps {
+    //level 0 is the texture itself, each one after that is half the size
+    //of the one before (rounded down), down to 1x1
+    pub levels: Vec<Bitmap>
+}
+
+impl Mipmaps {
+    pub fn new(texture: &Bitmap) -> Mipmaps {
+        let mut levels = vec![texture.clone()];
+        //an empty texture has nothing to average
+        let halvable = |l: &&Bitmap| l.width > 0 && l.height > 0 && (l.width > 1 || l.height > 1);
+        while let Some(level) = levels.last().filter(halvable) {
+            levels.push(downsample(level));
+        }
+        Mipmaps {levels}
+    }
+}
+
+//halves a bitmap, averaging each 2x2 block of texels (the odd row or
+//column at the edge of an odd sized bitmap gets folded into the last block)
+fn downsample(bitmap: &Bitmap) -> Bitmap {
+    let (width, height) = ((bitmap.width / 2).max(1), (bitmap.height / 2).max(1));
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let ys = (2 * y)..(if y == height - 1 {bitmap.height} else {2 * y + 2});
+            let xs = (2 * x)..(if x == width - 1 {bitmap.width} else {2 * x + 2});
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for sy in ys {
+                for sx in xs.clone() {
+                    let (r, g, b, a) = from_rgba_u8(bitmap.data[sy * bitmap.width + sx]);
+                    for (s, c) in sum.iter_mut().zip([r, g, b, a]) {
+                        *s += c as u32;
+                    }
+                    count += 1;
+                }
+            }
+            let [r, g, b, a] = sum.map(|s| ((s + count / 2) / count) as u8);
+            data.push(from_u8_rgba(r, g, b, a));
+        }
+    }
+    Bitmap {width, height, data}
+}
@@ -0,0 +1,128 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::File;
+use std::path::Path;
+
+use super::colors::{Color, from_rgba_u8};
+use super::bitmaps::{Bitmap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TgaCompression {
+    //image type 2
+    Uncompressed,
+    //image type 10
+    Rle
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TgaOrigin {
+    BottomLeft,
+    TopLeft
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TgaOptions {
+    pub compression: TgaCompression,
+    //32 bits per pixel if true, 24 otherwise
+    pub alpha: bool,
+    pub origin: TgaOrigin
+}
+
+impl Default for TgaOptions {
+    fn default() -> TgaOptions {
+        TgaOptions {
+            compression: TgaCompression::Rle,
+            alpha: true,
+            origin: TgaOrigin::TopLeft
+        }
+    }
+}
+
+//tga stores its pixels as bgr(a)
+fn encode(color: Color) -> [u8; 4] {
+    let (r, g, b, a) = from_rgba_u8(color);
+    [b, g, r, a]
+}
+
+pub fn save_bitmap_as_tga<P: AsRef<Path>>(bitmap: &Bitmap, filename: P, options: TgaOptions) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(filename)?);
+    write_tga(bitmap, &mut f, options)?;
+    f.flush()
+}
+
+pub fn write_tga<W: Write>(bitmap: &Bitmap, f: &mut W, options: TgaOptions) -> io::Result<()> {
+    if bitmap.width > u16::MAX as usize || bitmap.height > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}x{} is too large for a tga file", bitmap.width, bitmap.height)
+        ));
+    }
+
+    let bpp = if options.alpha {4} else {3};
+    let mut header = [0u8; 18];
+    header[2] = match options.compression {
+        TgaCompression::Uncompressed => 2,
+        TgaCompression::Rle => 10
+    };
+    header[12] = (bitmap.width & 255) as u8;
+    header[13] = (bitmap.width >> 8) as u8;
+    header[14] = (bitmap.height & 255) as u8;
+    header[15] = (bitmap.height >> 8) as u8;
+    header[16] = (bpp * 8) as u8;
+    //bits 0-3: alpha channel depth, bit 5: top-left origin
+    header[17] = if options.alpha {8} else {0};
+    if options.origin == TgaOrigin::TopLeft {
+        header[17] |= 1 << 5;
+    }
+    f.write_all(&header)?;
+
+    let mut out = Vec::with_capacity(bitmap.width * bpp + bitmap.width / 128 + 1);
+    for row in 0..bitmap.height {
+        let y = match options.origin {
+            TgaOrigin::TopLeft => row,
+            TgaOrigin::BottomLeft => bitmap.height - 1 - row
+        };
+        let line = &bitmap.data[y*bitmap.width..(y+1)*bitmap.width];
+        out.clear();
+
+        match options.compression {
+            TgaCompression::Uncompressed => {
+                for color in line {
+                    out.extend_from_slice(&encode(*color)[..bpp]);
+                }
+            },
+            TgaCompression::Rle => {
+                //packets never cross a scanline, as the spec recommends
+                let mut i = 0;
+                while i < line.len() {
+                    let mut run = 1;
+                    while i + run < line.len() && run < 128 && line[i + run] == line[i] {
+                        run += 1;
+                    }
+
+                    if run > 1 {
+                        out.push(128 + (run - 1) as u8);
+                        out.extend_from_slice(&encode(line[i])[..bpp]);
+                        i += run;
+                    } else {
+                        //gather pixels until the next run of at least two
+                        let mut raw = 1;
+                        while i + raw < line.len() && raw < 128
+                            && !(i + raw + 1 < line.len() && line[i + raw] == line[i + raw + 1]) {
+                            raw += 1;
+                        }
+                        out.push((raw - 1) as u8);
+                        for color in &line[i..i+raw] {
+                            out.extend_from_slice(&encode(*color)[..bpp]);
+                        }
+                        i += raw;
+                    }
+                }
+            }
+        }
+        f.write_all(&out)?;
+    }
+
+    Ok(())
+}
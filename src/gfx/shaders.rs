@@ -0,0 +1,324 @@
+use crate::math::{Vector3, Vector4, Matrix4,
+                  col_mat4_transform, vec3_add, vec3_sub, vec3_mul, vec3_scale, vec3_cross, vec3_square_len,
+                  vec3_normalized};
+use super::bitmaps::Bitmap;
+use super::colors::{Color, from_rgba_u8, from_u8_rgba};
+use super::lighting::{Light, Shading, Surface, Illumination, illuminate};
+use super::render_3d::{Model, Material, Fragment, BlendMode, tangent_frame};
+use super::sampler::{Sampler, Mipmaps};
+use std::slice;
+
+//what stays the same for every corner and pixel of an instance
+pub struct Uniforms<'a> {
+    pub model: &'a Model,
+    //model to camera space: the instance's scale, rotation and position,
+    //then the camera
+    pub to_camera: Matrix4,
+    //camera to clip space, see Projection::matrix
+    pub projection: Matrix4,
+    //see Projection::view_direction
+    pub view_direction: Option<Vector3>,
+    //Viewport::lights moved into camera space
+    pub lights: &'a [Light]
+}
+
+//one corner of one of the model's triangles, as handed to a VertexShader
+#[derive(Clone, Copy)]
+pub struct Corner<'a> {
+    pub uniforms: &'a Uniforms<'a>,
+    //into Model::triangles
+    pub triangle: usize,
+    //0, 1 or 2
+    pub corner: usize
+}
+
+impl<'a> Corner<'a> {
+    //in model space
+    pub fn position(&self) -> Vector4 {
+        let model = self.uniforms.model;
+        model.vertices[model.triangles[self.triangle].vertex[self.corner]]
+    }
+
+    //in camera space
+    pub fn eye(&self) -> Vector3 {
+        let eye = col_mat4_transform(self.uniforms.to_camera, self.position());
+        [eye[0], eye[1], eye[2]]
+    }
+
+    //None for faces without uv coordinates
+    pub fn uv(&self) -> Option<Vector3> {
+        let model = self.uniforms.model;
+        model.corner_uv(&model.triangles[self.triangle], self.corner)
+    }
+
+    //in model space; None for faces without vertex normals
+    pub fn normal(&self) -> Option<Vector3> {
+        let model = self.uniforms.model;
+        model.corner_normal(&model.triangles[self.triangle], self.corner)
+    }
+
+    //where the instance's transforms put the corner, which is what the
+    //built in shaders return
+    pub fn clip_position(&self) -> Vector4 {
+        let eye = self.eye();
+        col_mat4_transform(self.uniforms.projection, [eye[0], eye[1], eye[2], 1.0])
+    }
+}
+
+//runs for every corner of every triangle an instance draws
+pub trait VertexShader {
+    //the corner's position in clip space. Whatever the fragment shader
+    //needs interpolated across the triangle goes onto `varyings`, the same
+    //number of values for every corner.
+    fn shade(&self, corner: &Corner, varyings: &mut Vec<f64>) -> Vector4;
+
+    //whether every position lies inside the model's bounding sphere moved
+    //by the instance's transforms, which lets Instance::render skip
+    //instances that are out of view and the clipping of ones that are
+    //entirely in it. Shaders that push vertices further out return false.
+    fn stays_in_bounds(&self) -> bool {
+        true
+    }
+}
+
+//runs for every pixel that passes the depth test
+pub trait FragmentShader {
+    //the color of the pixel, or None to leave it out. `triangle` is an
+    //index into Model::triangles, and the fragment's varyings are the ones
+    //the vertex shader gave the triangle's corners.
+    fn shade(&self, uniforms: &Uniforms, triangle: usize, fragment: &Fragment) -> Option<Color>;
+
+    //how the colors cover what's already on screen; transparent triangles
+    //get drawn after the rest, back to front
+    fn blend_mode(&self) -> BlendMode {
+        BlendMode::Opaque
+    }
+}
+
+//what a SurfaceShader paints triangles with
+pub enum Fill<'texture> {
+    Solid(Color),
+    //one color for each triangle, see MaterialData::Flat
+    PerTriangle(&'texture [Color]),
+    //the levels of a mip chain, see Mipmaps::levels
+    Textured(&'texture [Bitmap])
+}
+
+//the shader behind every MaterialData besides Shaded: a fill lit by the
+//viewport's lights the way `shading` says
+pub struct SurfaceShader<'texture> {
+    pub fill: Fill<'texture>,
+    pub shading: Shading,
+    pub surface: Surface,
+    pub blend_mode: BlendMode,
+    //multiplies the alpha of textures
+    pub opacity: f64,
+    //multiplies the alpha of everything, see Material::alpha_map
+    pub alpha_map: Option<&'texture Bitmap>,
+    //for the texture and the maps alike
+    pub sampler: Sampler,
+    //only used with Shading::Phong, see Material
+    pub normal_map: Option<&'texture Bitmap>,
+    pub specular_map: Option<&'texture Bitmap>
+}
+
+impl<'texture> SurfaceShader<'texture> {
+    //no highlights, drawn opaque
+    fn plain(fill: Fill<'texture>, sampler: Sampler, shading: Shading) -> SurfaceShader<'texture> {
+        SurfaceShader {
+            fill,
+            shading,
+            surface: Surface {ambient: [1.0; 3], specular: [0.0; 3], shininess: 0.0},
+            blend_mode: BlendMode::Opaque,
+            opacity: 1.0,
+            alpha_map: None,
+            sampler,
+            normal_map: None,
+            specular_map: None
+        }
+    }
+
+    //MaterialData::Flat
+    pub fn flat(colors: &'texture [Color], shading: Shading) -> SurfaceShader<'texture> {
+        SurfaceShader::plain(Fill::PerTriangle(colors), Sampler::default(), shading)
+    }
+
+    //MaterialData::UV
+    pub fn uv(texture: &'texture Bitmap, sampler: Sampler, shading: Shading) -> SurfaceShader<'texture> {
+        SurfaceShader::plain(Fill::Textured(slice::from_ref(texture)), sampler, shading)
+    }
+
+    //MaterialData::MipmappedUV
+    pub fn mipmapped(mipmaps: &'texture Mipmaps, sampler: Sampler, shading: Shading) -> SurfaceShader<'texture> {
+        SurfaceShader::plain(Fill::Textured(&mipmaps.levels), sampler, shading)
+    }
+
+    //MaterialData::Material; `shading` is used unless the material has
+    //its own
+    pub fn material(material: &'texture Material, shading: Shading) -> SurfaceShader<'texture> {
+        SurfaceShader {
+            fill: match &material.diffuse_map {
+                Some(texture) => Fill::Textured(slice::from_ref(texture)),
+                None => Fill::Solid(material.diffuse_color())
+            },
+            shading: material.shading.unwrap_or(shading),
+            surface: Surface {ambient: material.ambient, specular: material.specular, shininess: material.shininess},
+            blend_mode: material.blend_mode,
+            opacity: material.opacity,
+            alpha_map: material.alpha_map.as_ref(),
+            sampler: material.sampler,
+            normal_map: material.normal_map.as_ref(),
+            specular_map: material.specular_map.as_ref()
+        }
+    }
+
+    //the maps only matter for per pixel lighting
+    fn maps(&self) -> (Option<&'texture Bitmap>, Option<&'texture Bitmap>) {
+        let phong = self.shading == Shading::Phong;
+        (self.normal_map.filter(|_| phong), self.specular_map.filter(|_| phong))
+    }
+
+    //whether the pixels need uv coordinates
+    fn sampled(&self) -> bool {
+        let (normal_map, specular_map) = self.maps();
+        matches!(self.fill, Fill::Textured(_)) || self.alpha_map.is_some() || normal_map.is_some() || specular_map.is_some()
+    }
+}
+
+impl<'texture> VertexShader for SurfaceShader<'texture> {
+    //the varyings are the uv coordinates if anything gets sampled, then
+    //the light at the corner for flat and Gouraud shading, or what lighting
+    //every pixel takes for Phong
+    fn shade(&self, corner: &Corner, varyings: &mut Vec<f64>) -> Vector4 {
+        let uniforms = corner.uniforms;
+        let eye = [0, 1, 2].map(|i| Corner {corner: i, ..*corner}.eye());
+        let face_normal = vec3_cross(vec3_sub(eye[1], eye[0]), vec3_sub(eye[2], eye[0]));
+        //faces without normals of their own (or models without any) use the
+        //face normal
+        let normal = corner.normal().map_or(face_normal, |n| {
+            let n = col_mat4_transform(uniforms.to_camera, [n[0], n[1], n[2], 0.0]);
+            [n[0], n[1], n[2]]
+        });
+
+        if self.sampled() {
+            let uv = corner.uv().unwrap_or([0.0; 3]);
+            varyings.extend([uv[0], uv[1]]);
+        }
+        let light = match self.shading {
+            Shading::Unlit => None,
+            Shading::Flat => {
+                let center = vec3_scale(vec3_add(vec3_add(eye[0], eye[1]), eye[2]), 1.0 / 3.0);
+                Some(illuminate(uniforms.lights, center, face_normal, uniforms.view_direction, &self.surface))
+            },
+            Shading::Gouraud => Some(illuminate(uniforms.lights, eye[corner.corner], normal, uniforms.view_direction, &self.surface)),
+            Shading::Phong => {
+                varyings.extend(eye[corner.corner]);
+                varyings.extend(normal);
+                if self.maps().0.is_some() {
+                    let model = uniforms.model;
+                    let tangent = match model.tangents.get(corner.triangle) {
+                        Some(tangents) => tangents[corner.corner],
+                        None => model.face_tangent(&model.triangles[corner.triangle])
+                    };
+                    let rotated = col_mat4_transform(uniforms.to_camera, [tangent[0], tangent[1], tangent[2], 0.0]);
+                    varyings.extend([rotated[0], rotated[1], rotated[2], tangent[3]]);
+                }
+                None
+            }
+        };
+        if let Some(light) = light {
+            varyings.extend(light.diffuse);
+            varyings.extend(light.specular);
+        }
+        corner.clip_position()
+    }
+}
+
+impl<'texture> FragmentShader for SurfaceShader<'texture> {
+    fn shade(&self, uniforms: &Uniforms, triangle: usize, fragment: &Fragment) -> Option<Color> {
+        let mut varyings = fragment.varyings;
+        let mut next = |count: usize| {
+            let (values, rest) = varyings.split_at(count);
+            varyings = rest;
+            values
+        };
+        let uv = if self.sampled() {
+            let uv = next(2);
+            (uv[0], uv[1])
+        } else {(0.0, 0.0)};
+
+        let mut color = match self.fill {
+            Fill::Solid(color) => color,
+            Fill::PerTriangle(colors) => *colors.get(triangle)?,
+            Fill::Textured(levels) => {
+                let color = self.sampler.sample_with_derivatives(
+                    levels, uv, fragment.derivative(0), fragment.derivative(1));
+                if self.opacity < 1.0 {scale_alpha(color, self.opacity)} else {color}
+            }
+        };
+        if let Some(map) = self.alpha_map {
+            let (r, g, b, a) = from_rgba_u8(self.sampler.sample(map, uv));
+            let coverage = (r as f64 + g as f64 + b as f64) / (3.0 * 255.0) * (a as f64 / 255.0);
+            color = scale_alpha(color, coverage);
+        }
+        let light = match self.shading {
+            Shading::Unlit => None,
+            Shading::Flat | Shading::Gouraud => {
+                let light = next(6);
+                Some(Illumination {diffuse: [light[0], light[1], light[2]], specular: [light[3], light[4], light[5]]})
+            },
+            Shading::Phong => {
+                let (normal_map, specular_map) = self.maps();
+                let eye = next(3);
+                let normal = next(3);
+                let mut normal = [normal[0], normal[1], normal[2]];
+                let mut surface = self.surface;
+                if let Some(map) = normal_map {
+                    let tangent = next(4);
+                    let tangent = [tangent[0], tangent[1], tangent[2], tangent[3]];
+                    normal = perturb_normal(normal, tangent, sample_map(map, &self.sampler, uv));
+                }
+                if let Some(map) = specular_map {
+                    surface.specular = vec3_mul(surface.specular, sample_map(map, &self.sampler, uv));
+                }
+                Some(illuminate(uniforms.lights, [eye[0], eye[1], eye[2]], normal, uniforms.view_direction, &surface))
+            }
+        };
+        if let Some(light) = light {
+            color = light.apply(color);
+        }
+        Some(color)
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+}
+
+fn scale_alpha(color: Color, factor: f64) -> Color {
+    let (r, g, b, a) = from_rgba_u8(color);
+    from_u8_rgba(r, g, b, (a as f64 * factor).round() as u8)
+}
+
+//the color at `uv` with its channels between 0 and 1
+fn sample_map(texture: &Bitmap, sampler: &Sampler, uv: (f64, f64)) -> Vector3 {
+    let (r, g, b, _) = from_rgba_u8(sampler.sample(texture, uv));
+    [r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0]
+}
+
+//bends `normal` by a texel of a tangent space normal map: red goes along
+//the tangent, green along the direction v increases in, blue along the
+//normal
+fn perturb_normal(normal: Vector3, tangent: Vector4, texel: Vector3) -> Vector3 {
+    //interpolation leaves both a bit off unit length and perpendicular
+    let frame = tangent_frame(normal, [tangent[0], tangent[1], tangent[2]], [0.0; 3]);
+    if frame == [0.0; 4] || vec3_square_len(normal) == 0.0 {
+        return normal;
+    }
+    let normal = vec3_normalized(normal);
+    let handedness = if tangent[3] < 0.0 {-1.0} else {1.0};
+    let tangent = [frame[0], frame[1], frame[2]];
+    let bitangent = vec3_scale(vec3_cross(normal, tangent), handedness);
+    let [x, y, z] = texel.map(|c| c * 2.0 - 1.0);
+    vec3_add(vec3_add(vec3_scale(tangent, x), vec3_scale(bitangent, y)), vec3_scale(normal, z))
+}
@@ -0,0 +1,536 @@
+use super::image_error::{ImageError, ImageResult};
+
+//deflate (RFC 1951) wrapped in the zlib container (RFC 1950), which is all
+//png needs. The decoder handles every block type, the encoder sticks to
+//fixed huffman codes with a hash chain match finder.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+//order in which the code length code lengths are stored
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15
+];
+
+const MAX_BITS: usize = 15;
+const WINDOW_SIZE: usize = 32768;
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    //5552 is the largest n for which b can't overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+fn corrupt(msg: &str) -> ImageError {
+    ImageError::Corrupt(format!("deflate stream: {}", msg))
+}
+
+struct BitReader<'data> {
+    data: &'data [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32
+}
+
+impl<'data> BitReader<'data> {
+    fn new(data: &'data [u8]) -> BitReader<'data> {
+        BitReader {
+            data,
+            pos: 0,
+            bit_buffer: 0,
+            bit_count: 0
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> ImageResult<u32> {
+        while self.bit_count < n {
+            let byte = *self.data.get(self.pos).ok_or(ImageError::Truncated)?;
+            self.pos += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u64 << n) - 1) as u32;
+        self.bit_buffer >>= n;
+        self.bit_count -= n;
+        Ok(value)
+    }
+
+    //drops the rest of the current byte, used by stored blocks
+    fn align(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+//canonical huffman code stored as the number of codes of each length and
+//the symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> ImageResult<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        //an over-subscribed set of lengths can't be a prefix code
+        let mut left: i32 = 1;
+        for count in &counts[1..] {
+            left <<= 1;
+            left -= *count as i32;
+            if left < 0 {
+                return Err(corrupt("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_BITS + 1] as usize];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Ok(Huffman {counts, symbols})
+    }
+
+    fn decode(&self, input: &mut BitReader) -> ImageResult<u16> {
+        //codes are stored most significant bit first, one bit at a time
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid huffman code"))
+    }
+}
+
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut lit = [0u8; 288];
+    for (symbol, len) in lit.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8
+        };
+    }
+    (lit, [5u8; 30])
+}
+
+fn too_long() -> ImageError {
+    corrupt("more data than expected")
+}
+
+fn inflate_block(input: &mut BitReader, out: &mut Vec<u8>, limit: usize, lit: &Huffman, dist: &Huffman) -> ImageResult<()> {
+    loop {
+        let symbol = lit.decode(input)? as usize;
+        if symbol < 256 {
+            if out.len() >= limit {
+                return Err(too_long());
+            }
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= 29 {
+                return Err(corrupt("invalid length symbol"));
+            }
+            let length = LENGTH_BASE[symbol] as usize
+                + input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = dist.decode(input)? as usize;
+            if symbol >= 30 {
+                return Err(corrupt("invalid distance symbol"));
+            }
+            let distance = DIST_BASE[symbol] as usize
+                + input.bits(DIST_EXTRA[symbol] as u32)? as usize;
+            if distance > out.len() {
+                return Err(corrupt("distance reaches before the start of the data"));
+            }
+            if out.len() + length > limit {
+                return Err(too_long());
+            }
+
+            //the copy may overlap with itself, so go byte by byte
+            let start = out.len() - distance;
+            for i in 0..length {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+fn read_dynamic_tables(input: &mut BitReader) -> ImageResult<(Huffman, Huffman)> {
+    let hlit = input.bits(5)? as usize + 257;
+    let hdist = input.bits(5)? as usize + 1;
+    let hclen = input.bits(4)? as usize + 4;
+
+    let mut clen_lengths = [0u8; 19];
+    for index in &CLEN_ORDER[..hclen] {
+        clen_lengths[*index] = input.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clen_lengths)?;
+
+    let mut lengths = vec![0u8; hlit + hdist];
+    let mut i = 0;
+    while i < hlit + hdist {
+        let symbol = clen.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(corrupt("repeat with no previous length"));
+                }
+                (lengths[i - 1], 3 + input.bits(2)? as usize)
+            },
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize)
+        };
+        if i + repeat > hlit + hdist {
+            return Err(corrupt("code lengths overflow the table"));
+        }
+        for len in &mut lengths[i..i+repeat] {
+            *len = value;
+        }
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("no end of block code"));
+    }
+
+    Ok((Huffman::new(&lengths[..hlit])?, Huffman::new(&lengths[hlit..])?))
+}
+
+//`limit` is the most output the caller can make sense of; a stream that
+//would go past it is rejected as soon as it does, instead of growing
+//without bound
+pub fn inflate(data: &[u8], limit: usize) -> ImageResult<Vec<u8>> {
+    let mut input = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let header = data.get(input.pos..input.pos+4).ok_or(ImageError::Truncated)?;
+                let len = (header[1] as usize) << 8 | header[0] as usize;
+                let nlen = (header[3] as usize) << 8 | header[2] as usize;
+                if len != !nlen & 0xffff {
+                    return Err(corrupt("stored block length mismatch"));
+                }
+                input.pos += 4;
+                let block = data.get(input.pos..input.pos+len).ok_or(ImageError::Truncated)?;
+                if out.len() + len > limit {
+                    return Err(too_long());
+                }
+                out.extend_from_slice(block);
+                input.pos += len;
+            },
+            1 => {
+                let (lit, dist) = fixed_lengths();
+                inflate_block(&mut input, &mut out, limit, &Huffman::new(&lit)?, &Huffman::new(&dist)?)?;
+            },
+            2 => {
+                let (lit, dist) = read_dynamic_tables(&mut input)?;
+                inflate_block(&mut input, &mut out, limit, &lit, &dist)?;
+            },
+            _ => return Err(corrupt("invalid block type"))
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+//see inflate for `limit`
+pub fn zlib_decompress(data: &[u8], limit: usize) -> ImageResult<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(ImageError::Truncated);
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 15 != 8 || cmf >> 4 > 7 {
+        return Err(ImageError::Corrupt("zlib stream: not deflate compressed".to_string()));
+    }
+    if !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err(ImageError::Corrupt("zlib stream: bad header check".to_string()));
+    }
+    if flg & 32 != 0 {
+        return Err(ImageError::Corrupt("zlib stream: preset dictionaries are not allowed".to_string()));
+    }
+
+    let out = inflate(&data[2..], limit)?;
+
+    let tail = &data[data.len()-4..];
+    let checksum = (tail[0] as u32) << 24 | (tail[1] as u32) << 16 | (tail[2] as u32) << 8 | tail[3] as u32;
+    if checksum != adler32(&out) {
+        return Err(ImageError::Corrupt("zlib stream: adler32 mismatch".to_string()));
+    }
+    Ok(out)
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, n: u32) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += n;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    //huffman codes go out most significant bit first
+    fn code(&mut self, code: u32, len: u32) {
+        let mut reversed = 0;
+        for i in 0..len {
+            reversed |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.bits(reversed, len);
+    }
+
+    fn flush(&mut self) {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buffer as u8);
+        }
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+fn write_literal(w: &mut BitWriter, symbol: usize) {
+    match symbol {
+        0..=143 => w.code(0x30 + symbol as u32, 8),
+        144..=255 => w.code(0x190 + (symbol - 144) as u32, 9),
+        256..=279 => w.code((symbol - 256) as u32, 7),
+        _ => w.code(0xc0 + (symbol - 280) as u32, 8)
+    }
+}
+
+fn write_match(w: &mut BitWriter, length: usize, distance: usize) {
+    let symbol = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap();
+    write_literal(w, 257 + symbol);
+    w.bits((length - LENGTH_BASE[symbol] as usize) as u32, LENGTH_EXTRA[symbol] as u32);
+
+    let symbol = DIST_BASE.iter().rposition(|base| *base as usize <= distance).unwrap();
+    w.code(symbol as u32, 5);
+    w.bits((distance - DIST_BASE[symbol] as usize) as u32, DIST_EXTRA[symbol] as u32);
+}
+
+//how many earlier positions with the same hash we are willing to check
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        bit_buffer: 0,
+        bit_count: 0
+    };
+    //a single final block with fixed codes
+    w.bits(1, 1);
+    w.bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, pos: usize| {
+        if pos + 2 < data.len() {
+            let h = hash(data, pos);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if pos + 2 < data.len() {
+            let mut candidate = head[hash(data, pos)];
+            let max_len = (data.len() - pos).min(258);
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate < WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..].iter()
+                    .zip(&data[pos..pos+max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= 3 {
+            write_match(&mut w, best_len, best_dist);
+            for p in pos..pos+best_len {
+                insert(&mut head, &mut prev, p);
+            }
+            pos += best_len;
+        } else {
+            write_literal(&mut w, data[pos] as usize);
+            insert(&mut head, &mut prev, pos);
+            pos += 1;
+        }
+    }
+
+    write_literal(&mut w, 256);
+    w.flush();
+    w.out
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    //deflate with a 32K window, default compression level
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //pseudo random bytes, so there's something deflate can't shrink
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn pangrams() -> Vec<u8> {
+        let mut text = "The quick brown fox jumps over the lazy dog. ".repeat(3);
+        text.push_str("Pack my box with five dozen liquor jugs! 0123456789");
+        text.into_bytes()
+    }
+
+    #[test]
+    fn round_trips() {
+        let long_run = vec![7u8; 100_000];
+        //matches further back than the window
+        let far_repeat = [noise(40_000), noise(40_000)].concat();
+        for data in [vec![], b"a".to_vec(), pangrams(), noise(5000), long_run, far_repeat] {
+            assert_eq!(zlib_decompress(&zlib_compress(&data), data.len()).unwrap(), data);
+        }
+    }
+
+    //the streams below come from the reference zlib (python's
+    //zlib.compress), one per block type
+
+    #[test]
+    fn decodes_stored_block() {
+        let stream = [
+            0x78, 0x01, 0x01, 0x0c, 0x00, 0xf3, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65,
+            0x64, 0x20, 0x62, 0x6c, 0x6f, 0x63, 0x6b, 0x1f, 0x80, 0x04, 0xbd
+        ];
+        assert_eq!(zlib_decompress(&stream, 12).unwrap(), b"stored block");
+    }
+
+    #[test]
+    fn decodes_fixed_huffman_block() {
+        let stream = [
+            0x78, 0xda, 0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x85, 0x8c, 0xd4, 0x9c,
+            0x9c, 0x7c, 0x08, 0x09, 0x00, 0x70, 0x12, 0x09, 0x01
+        ];
+        assert_eq!(zlib_decompress(&stream, usize::MAX).unwrap(), b"abcabcabcabc hello hello");
+    }
+
+    #[test]
+    fn decodes_dynamic_huffman_block() {
+        let stream = [
+            0x78, 0xda, 0xb5, 0xcb, 0xd9, 0x15, 0x40, 0x30, 0x14, 0x45, 0xd1, 0x56,
+            0xae, 0x06, 0x2c, 0xf3, 0xd0, 0x85, 0x0f, 0x0d, 0x04, 0x41, 0x4c, 0x8f,
+            0x90, 0x20, 0xd5, 0x7b, 0x4d, 0xf8, 0x3e, 0xfb, 0xd4, 0xa3, 0xc4, 0x61,
+            0x54, 0x3b, 0xa3, 0xd1, 0x74, 0x6f, 0xe8, 0xe9, 0xc1, 0x64, 0xd6, 0xfd,
+            0x04, 0x59, 0xa9, 0x71, 0x71, 0x5e, 0x84, 0x7b, 0xd1, 0xd1, 0xe0, 0xa3,
+            0xfe, 0x0d, 0x57, 0x82, 0xdd, 0xfa, 0xa2, 0x61, 0x74, 0xab, 0x6b, 0x44,
+            0xaf, 0xac, 0xe4, 0xe4, 0xe4, 0x86, 0x45, 0x1d, 0x86, 0x34, 0xbf, 0xc3,
+            0xe9, 0x21, 0x08, 0xa3, 0x38, 0x49, 0xb3, 0xbc, 0x28, 0x3f, 0x74, 0x70,
+            0x41, 0x2d
+        ];
+        assert_eq!(zlib_decompress(&stream, usize::MAX).unwrap(), pangrams());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut stream = zlib_compress(b"checksum");
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(matches!(zlib_decompress(&stream, usize::MAX), Err(ImageError::Corrupt(_))));
+    }
+
+    #[test]
+    fn rejects_output_over_limit() {
+        //a stored block, a literal and a long match each have their own check
+        let stored = [
+            0x78, 0x01, 0x01, 0x0c, 0x00, 0xf3, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65,
+            0x64, 0x20, 0x62, 0x6c, 0x6f, 0x63, 0x6b, 0x1f, 0x80, 0x04, 0xbd
+        ];
+        assert!(matches!(zlib_decompress(&stored, 11), Err(ImageError::Corrupt(_))));
+        let text = zlib_compress(b"a");
+        assert!(matches!(zlib_decompress(&text, 0), Err(ImageError::Corrupt(_))));
+        let run = zlib_compress(&[7u8; 100_000]);
+        assert!(matches!(zlib_decompress(&run, 99_999), Err(ImageError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,4 @@
+extern crate vecmath;
+
+pub mod math;
+pub mod gfx;
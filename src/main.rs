@@ -1,24 +1,22 @@
 extern crate minifb;
-extern crate vecmath;
-mod math;
-mod gfx;
+extern crate learning_graphics;
 
 
 use minifb::{Key, Window, WindowOptions, MouseMode};
-use gfx::colors::{Color, from_u8_rgb, from_u8_rgba};
+use learning_graphics::gfx::colors::{Color, from_u8_rgb, from_u8_rgba};
 
-use gfx::bitmaps::Bitmap;
-use gfx::font::{Font};
-use gfx::load_tga::{load_bitmap_from_tga};
-use gfx::render_2d::{Polygon2D, TexturedFlat2D, Surface2D, TexturedPolygon2D, Viewport};
+use learning_graphics::gfx::bitmaps::Bitmap;
+use learning_graphics::gfx::font::{Font};
+use learning_graphics::gfx::load_tga::{load_bitmap_from_tga};
+use learning_graphics::gfx::render_2d::{Polygon2D, TexturedFlat2D, Surface2D, TexturedPolygon2D, Viewport};
 
-use gfx::render_3d::{Model, Instance, MaterialData, PolygonData};
+use learning_graphics::gfx::render_3d::{Model, Instance, MaterialData, CullMode, Camera};
+use learning_graphics::gfx::lighting::Shading;
+use learning_graphics::gfx::sampler::Sampler;
 
-use gfx::model_loading::load_obj_file;
+use learning_graphics::gfx::primitives::putpixel;
 
-use gfx::primitives::putpixel;
-
-use math::{col_mat4_mul};
+use learning_graphics::math::{col_mat4_mul};
 
 const WINDOW_WIDTH: usize = 640*2;
 const WINDOW_HEIGHT: usize = 640*2;
@@ -29,8 +27,6 @@ const VIEWPORT_HEIGHT: f64 = 6.0;
 
 const BACKGROUND_COLOR: Color = from_u8_rgb(255, 255, 255);
 
-use std::fs;
-
 fn main() {
     
     let textmap = load_bitmap_from_tga("bizcat.tga").unwrap();
@@ -69,46 +65,7 @@ fn main() {
         (poly2, from_u8_rgb(255, 0, 0))
     ];
 
-    let cube_model_old = Model {
-        vertices: vec![
-            [1.0, 1.0, 1.0, 1.0],
-            [-1.0, 1.0, 1.0, 1.0],
-            [-1.0, -1.0, 1.0, 1.0],
-            [1.0, -1.0, 1.0, 1.0],
-            [1.0, 1.0, -1.0, 1.0],
-            [-1.0, 1.0, -1.0, 1.0],
-            [-1.0, -1.0, -1.0, 1.0],
-            [1.0, -1.0, -1.0, 1.0]
-        ],
-        vertex_normals: vec![],
-        uv_map: 
-            vec![
-                [0.0, 1.0, 0.0],
-                [1.0, 1.0, 0.0],
-                [0.0, 0.0, 0.0],
-                [1.0, 0.0, 0.0],
-                [0.0, 0.0, 0.0],
-                [1.0, 0.0, 0.0],
-                [0.0, 1.0, 0.0],
-                [1.0, 1.0, 0.0],
-            ],
-        triangles: vec![
-    PolygonData {vertex:[0, 1, 2],normal:[0,0,0],uv_coord:[0, 1, 2]}, 
-    PolygonData {vertex:[0, 2, 3],normal:[0,0,0],uv_coord:[0, 2, 3]}, 
-    PolygonData {vertex:[4, 0, 3],normal:[0,0,0],uv_coord:[4, 0, 3]}, 
-    PolygonData {vertex:[4, 3, 7],normal:[0,0,0],uv_coord:[4, 3, 7]}, 
-    PolygonData {vertex:[5, 4, 7],normal:[0,0,0],uv_coord:[5, 4, 7]}, 
-    PolygonData {vertex:[5, 7, 6],normal:[0,0,0],uv_coord:[5, 7, 6]}, 
-    PolygonData {vertex:[1, 5, 6],normal:[0,0,0],uv_coord:[1, 5, 6]}, 
-    PolygonData {vertex:[1, 6, 2],normal:[0,0,0],uv_coord:[1, 6, 2]}, 
-    PolygonData {vertex:[4, 5, 1],normal:[0,0,0],uv_coord:[4, 5, 1]}, 
-    PolygonData {vertex:[4, 1, 0],normal:[0,0,0],uv_coord:[4, 1, 0]}, 
-    PolygonData {vertex:[2, 6, 7],normal:[0,0,0],uv_coord:[2, 6, 7]}, 
-    PolygonData {vertex:[2, 7, 3],normal:[0,0,0],uv_coord:[2, 7, 3]}, 
-        ]
-    };
-
-    let cube_model = load_obj_file(&fs::read_to_string("cube.obj").unwrap());
+    let cube_model = Model::cube();
     
     println!("{:?}", cube_model);
     
@@ -149,8 +106,9 @@ fn main() {
         z: 0.0,
         material:
         MaterialData::UV(
-            &texture
-        )
+            &texture,
+            Sampler::default()
+        ),
 //        MaterialData::Flat(vec![
 //            from_u8_rgb(255, 0, 0),
 //            from_u8_rgb(255, 0, 0),
@@ -164,9 +122,13 @@ fn main() {
 //            from_u8_rgb(230,230,250),
 //            from_u8_rgb(0, 255, 255),
 //            from_u8_rgb(0, 255, 255)
-//        ])
+//        ]),
+        cull_mode: CullMode::Back,
+        shading: Shading::Unlit
     };
     
+    let camera = Camera::new([0.0, 0.0, -3.0]);
+
     while window.is_open() && !window.is_key_down(Key::Escape) {
         
        
@@ -228,15 +190,7 @@ fn main() {
         }
         
         
-        cube_instance.render(&mut viewport,
-                             [
-                                 [1.0, 0.0, 0.0, 0.0],
-                                 [0.0, 1.0, 0.0, 0.0], 
-                                 [0.0, 0.0, 1.0, 0.0], 
-                                 [0.0, 0.0, 3.0, 1.0], 
-                                 
-                             ],
-        );
+        cube_instance.render(&mut viewport, camera.view_matrix());
 
          let val = if let Some(v) = viewport.get_dbuff_val(mouse_x, mouse_y) {
            *v
@@ -264,11 +218,7 @@ fn main() {
         
         // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
         
-        window
-            .update_with_buffer(&(viewport.screen.data), WINDOW_WIDTH, WINDOW_HEIGHT)
-            .unwrap();
-        
-        viewport.clear_screen();
+        viewport.present(&mut window).unwrap();
     }
 }
 